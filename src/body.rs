@@ -1,28 +1,202 @@
-use ggez::nalgebra::{Point2, Vector2};
+use ggez::nalgebra::{Point3, Vector3};
 
-// All points/vectors are in reference to global xy-plane
+use crate::spacecraft::Spacecraft;
+
+pub const G: f64 = 6.67e-11;
+pub const C: f64 = 299_792_458.0; // Speed of light
+pub const M_SUN: f64 = 1.989e+30;
+pub const L_SUN: f64 = 3.828e+26; // In watts
+
+// Drag coefficient of every body, roughly that of a sphere at high speed
+const DRAG_COEFFICIENT: f64 = 1.0;
+
+// Exponential atmosphere around a body, which slows down anything passing
+// through it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    pub density: f64, // At the surface, in kg/m^3
+    pub scale_height: f64 // Height over which the density falls by a factor e
+}
+
+impl Atmosphere {
+    // Density at `altitude` metres above the surface. Below the surface it
+    // is as dense as at the surface
+    pub fn density_at(&self, altitude: f64) -> f64 {
+        if self.scale_height <= 0.0 { return 0.0; }
+        self.density * (-altitude.max(0.0) / self.scale_height).exp()
+    }
+}
+
+// All points/vectors are in global coordinates, with 2D setups lying in
+// the xy-plane
 // For convenience, 1 unit = 1 metre
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Body {
-    pub mass: f32,
-    pub pos: Point2<f32>,
-    pub v: Vector2<f32>,
-    pub a: Vector2<f32>,
+    pub mass: f64,
+    pub pos: Point3<f64>,
+    pub v: Vector3<f64>,
+    pub a: Vector3<f64>,
+    pub jerk: Vector3<f64>, // Rate of change of `a`, only kept up to date by Hermite
+    pub radius: f64,
+    pub softening: Option<f64>, // Overrides the global softening length
+    pub pinned: bool, // Held in place, though it still pulls on everything else
+    pub test_particle: bool, // Massless, feels the pull of other bodies but exerts none
+    pub atmosphere: Option<Atmosphere>,
+    pub luminosity: f64, // In watts
+    // Radiation pressure over gravity for this body near the Sun, which
+    // only matters for dust grains
+    pub beta: f64,
+    pub spacecraft: Option<Spacecraft>, // Engine and flight plan, if it can manoeuvre
     pub color: [f32; 4]
 }
 
 impl Body {
-    pub fn accel_towards(&self, other: &Body) -> Vector2<f32> {
-        let dx = other.pos.x - self.pos.x;
-        let dy = other.pos.y - self.pos.y;
+    // Plain white body with nothing special about it
+    pub fn new(mass: f64, radius: f64, pos: Point3<f64>, v: Vector3<f64>) -> Body {
+        Body {
+            mass, radius, pos, v,
+            a: Vector3::new(0.0, 0.0, 0.0),
+            jerk: Vector3::new(0.0, 0.0, 0.0),
+            softening: None,
+            pinned: false,
+            test_particle: false,
+            atmosphere: None,
+            luminosity: 0.0,
+            beta: 0.0,
+            spacecraft: None,
+            color: [1.0, 1.0, 1.0, 1.0]
+        }
+    }
+
+    // Squared softening length used between this body and another with
+    // squared softening `other_eps_squared`
+    pub fn pair_softening(&self, softening: f64, other_eps_squared: f64) -> f64 {
+        let eps = self.softening.unwrap_or(softening);
+        (eps * eps + other_eps_squared) / 2.0
+    }
+
+    // Pairwise forces take the separation `d` from this body to `other`
+    // rather than their positions, so in a periodic domain it can be that
+    // of the nearest image. `softening` is the global softening length,
+    // used unless either body has its own
+    pub fn accel_towards(&self, other: &Body, d: &Vector3<f64>, softening: f64) -> Vector3<f64> {
+        let other_eps = other.softening.unwrap_or(softening);
+        let eps_squared = self.pair_softening(softening, other_eps * other_eps);
+        point_mass_accel(d, other.mass, eps_squared)
+    }
+
+    // Acceleration towards `other` along with its time derivative (jerk)
+    pub fn accel_jerk_towards(&self, other: &Body, d: &Vector3<f64>,
+                              softening: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let u = other.v - self.v;
+
+        let r_squared = d.norm_squared();
+        if r_squared == 0.0 { return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)); }
+
+        let other_eps = other.softening.unwrap_or(softening);
+        let r_squared = r_squared + self.pair_softening(softening, other_eps * other_eps);
+        let gm_over_r3 = G * other.mass / (r_squared * r_squared.sqrt());
+        let a = gm_over_r3 * d;
+        let jerk = gm_over_r3 * (u - 3.0 * d.dot(&u) / r_squared * d);
+        (a, jerk)
+    }
+
+    // Drag from moving through the atmosphere of `other`, which moves along
+    // with it. Bodies without mass or size feel no drag
+    pub fn drag_accel(&self, other: &Body, d: &Vector3<f64>) -> Vector3<f64> {
+        let atmosphere = match other.atmosphere {
+            Some(atmosphere) => atmosphere,
+            None => return Vector3::new(0.0, 0.0, 0.0)
+        };
+        if self.mass <= 0.0 || self.radius <= 0.0 { return Vector3::new(0.0, 0.0, 0.0); }
+
+        let altitude = d.norm() - other.radius;
+        let density = atmosphere.density_at(altitude);
+        let area = std::f64::consts::PI * self.radius * self.radius;
+        let v = self.v - other.v;
+        -0.5 * DRAG_COEFFICIENT * density * area / self.mass * v.norm() * v
+    }
+
+    // Counterpart of G M for the radiation pressure the light of this body
+    // exerts on a particle with the given beta
+    pub fn radiation_gm(&self, beta: f64) -> f64 {
+        beta * G * M_SUN * self.luminosity / L_SUN
+    }
+
+    // Radiation pressure and Poynting-Robertson drag from the light of
+    // `other` (Burns, Lamy & Soter 1979), along with the jerk of the
+    // pressure. The drag is of order v/c, so it's left out of the jerk
+    pub fn radiation_accel_jerk(&self, other: &Body,
+                                d: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+        let k = other.radiation_gm(self.beta);
+        let d = -d;
+        let u = self.v - other.v;
+
+        let r_squared = d.norm_squared();
+        if k == 0.0 || r_squared == 0.0 {
+            return (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        }
 
-        let r_squared = dx.powi(2) + dy.powi(2);
-        if r_squared == 0.0 { return Vector2::new(0.0, 0.0); }
+        let r = r_squared.sqrt();
+        let k_over_r2 = k / r_squared;
+        let a = k_over_r2 * ((1.0 - d.dot(&u) / (r * C)) * d / r - u / C);
+        let jerk = k_over_r2 / r * (u - 3.0 * d.dot(&u) / r_squared * d);
+        (a, jerk)
+    }
+
+    // Acceleration from the engine, if this is a spacecraft in the middle of
+    // a burn. The burn direction is taken relative to `primary`, the body
+    // it orbits and its separation, or to the fixed axes if there isn't one
+    pub fn thrust_accel(&self, primary: Option<(&Body, Vector3<f64>)>) -> Vector3<f64> {
+        let (thrust, burn) = match &self.spacecraft {
+            Some(Spacecraft { thrust, burn: Some(burn), .. }) => (*thrust, *burn),
+            _ => return Vector3::new(0.0, 0.0, 0.0)
+        };
+        let (r, v) = match primary {
+            Some((primary, d)) => (-d, self.v - primary.v),
+            None => (self.pos.coords, self.v)
+        };
+
+        // Prograde along the velocity, normal along the angular momentum and
+        // radial at right angles to both, on the side facing away from the
+        // primary. Moving straight towards or away from the primary leaves
+        // the orbital plane undefined, so any plane through the velocity
+        // will do
+        let prograde = if v.norm_squared() > 0.0 { v.normalize() }
+                       else { Vector3::new(1.0, 0.0, 0.0) };
+        let normal = [r.cross(&v), prograde.cross(&Vector3::z()), prograde.cross(&Vector3::x())]
+            .iter()
+            .find(|n| n.norm_squared() > 0.0)
+            .map_or(Vector3::z(), |n| n.normalize());
+        let radial = prograde.cross(&normal);
+        thrust * (burn.x * prograde + burn.y * radial + burn.z * normal)
+    }
 
-        let g = 6.67e-11_f32;
-        let a = g * other.mass / r_squared;
+    // First post-Newtonian correction to the pull of `other`, treating it
+    // as the mass this body orbits (the test particle limit of the
+    // Einstein-Infeld-Hoffmann equations). This is what makes Mercury's
+    // perihelion advance by 43" per century
+    pub fn relativistic_accel_towards(&self, other: &Body, d: &Vector3<f64>) -> Vector3<f64> {
+        let r = -d;
+        let v = self.v - other.v;
 
-        let theta = dy.atan2(dx);
-        Vector2::new(a * theta.cos(), a * theta.sin())
+        let r_squared = r.norm_squared();
+        if r_squared == 0.0 { return Vector3::new(0.0, 0.0, 0.0); }
+
+        let r_norm = r_squared.sqrt();
+        let gm = G * other.mass;
+        gm / (C * C * r_squared * r_norm)
+            * ((4.0 * gm / r_norm - v.norm_squared()) * r + 4.0 * r.dot(&v) * v)
     }
 }
+
+// Acceleration due to a point mass `d` away, Plummer softened by
+// `eps_squared` so close encounters don't blow up
+pub fn point_mass_accel(d: &Vector3<f64>, mass: f64, eps_squared: f64) -> Vector3<f64> {
+    let r_squared = d.norm_squared();
+    if r_squared == 0.0 { return Vector3::new(0.0, 0.0, 0.0); }
+
+    let r_squared = r_squared + eps_squared;
+    let a = G * mass / r_squared;
+    a / r_squared.sqrt() * d
+}
@@ -0,0 +1,119 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+use crate::body::Body;
+use crate::forces::Forces;
+use crate::integrator::{adaptive_advance, Attempt, IntegratorState};
+
+// Number of midpoint substeps for each row of the extrapolation table
+const SUBSTEPS: [u32; 8] = [2, 4, 6, 8, 10, 12, 14, 16];
+
+// Fraction of the step size the error estimate allows that is taken
+const SAFETY: f64 = 0.9;
+
+// Rejected steps shrink at least this much, as the row that failed is
+// likely to fail again on a step only slightly shorter
+const MAX_REJECTED_FACTOR: f64 = 0.5;
+
+// Positions and velocities of all bodies
+type Phase = Vec<(Vector3<f64>, Vector3<f64>)>;
+
+// Advance `bodies` by `duration` seconds (negative to go backwards) with
+// the Gragg-Bulirsch-Stoer method: each step is taken with the modified
+// midpoint method using more and more substeps, and the results are
+// extrapolated to zero substep size until they agree to within the
+// tolerances in `state`
+pub fn advance(bodies: &mut [Body], duration: f64, state: &mut IntegratorState,
+               forces: &Forces) -> f64 {
+    if bodies.is_empty() { return duration; }
+    forces.update_accels(bodies);
+    adaptive_advance(duration, state, |h, state| {
+        let (trial, err, row) = try_step(bodies, h, state, forces);
+        // The estimate from row k is of order 2k + 1 in the step size
+        let factor = SAFETY * err.powf(-1.0 / (2 * row + 1) as f64);
+        if err > 1.0 {
+            return Attempt { accepted: false, factor: factor.min(MAX_REJECTED_FACTOR) };
+        }
+
+        for (b, &(pos, v)) in bodies.iter_mut().zip(&trial) {
+            b.pos = Point3::from(pos);
+            b.v = v;
+        }
+        forces.update_accels(bodies);
+        Attempt { accepted: true, factor }
+    })
+}
+
+// Returns the extrapolated solution, its scaled error norm, and the row of
+// the table it came from. Stops at the first row that meets the tolerances.
+// Accelerations in `bodies` must be up to date
+fn try_step(bodies: &[Body], h: f64, state: &IntegratorState,
+            forces: &Forces) -> (Phase, f64, usize) {
+    let mut prev_row: Vec<Phase> = Vec::new();
+    let mut err = f64::INFINITY;
+    for (k, &n) in SUBSTEPS.iter().enumerate() {
+        let mut row = vec![midpoint(bodies, h, n, forces)];
+        // Richardson extrapolation in h^2, as the midpoint method's error
+        // only has even powers
+        for j in 1..=k {
+            let ratio = (n as f64 / SUBSTEPS[k - j] as f64).powi(2) - 1.0;
+            let next = row[j - 1].iter().zip(&prev_row[j - 1])
+                .map(|(&(pos, v), &(pos_, v_))| (pos + (pos - pos_) / ratio, v + (v - v_) / ratio))
+                .collect();
+            row.push(next);
+        }
+
+        if k > 0 {
+            err = error(bodies, &row[k], &row[k - 1], state);
+            if err <= 1.0 {
+                return (row.swap_remove(k), err, k);
+            }
+        }
+        prev_row = row;
+    }
+
+    let last = prev_row.len() - 1;
+    (prev_row.swap_remove(last), err, last)
+}
+
+// Modified midpoint method over `h` seconds with `n` substeps, with Gragg's
+// smoothing at the end
+fn midpoint(bodies: &[Body], h: f64, n: u32, forces: &Forces) -> Phase {
+    let dt = h / n as f64;
+    let mut prev = bodies.to_vec();
+    let mut cur = bodies.to_vec();
+    for b in cur.iter_mut() {
+        b.pos += dt * b.v;
+        b.v += dt * b.a;
+    }
+
+    for _ in 1..n {
+        forces.update_accels(&mut cur);
+        for (p, c) in prev.iter_mut().zip(cur.iter_mut()) {
+            let pos = p.pos + 2.0 * dt * c.v;
+            let v = p.v + 2.0 * dt * c.a;
+            p.pos = c.pos;
+            p.v = c.v;
+            c.pos = pos;
+            c.v = v;
+        }
+    }
+
+    forces.update_accels(&mut cur);
+    cur.iter().zip(&prev)
+        .map(|(c, p)| {
+            (0.5 * (c.pos.coords + p.pos.coords + dt * c.v), 0.5 * (c.v + p.v + dt * c.a))
+        })
+        .collect()
+}
+
+// Root mean square of the difference between two estimates, scaled by the
+// tolerances
+fn error(bodies: &[Body], y: &Phase, y_: &Phase, state: &IntegratorState) -> f64 {
+    let mut sum = 0.0;
+    for (old, (&(pos, v), &(pos_, v_))) in bodies.iter().zip(y.iter().zip(y_)) {
+        let scale_pos = state.atol + state.rtol * old.pos.coords.norm().max(pos.norm());
+        let scale_v = state.atol + state.rtol * old.v.norm().max(v.norm());
+        sum += ((pos - pos_).norm() / scale_pos).powi(2) + ((v - v_).norm() / scale_v).powi(2);
+    }
+    (sum / (2 * bodies.len()) as f64).sqrt()
+}
@@ -0,0 +1,41 @@
+use ggez::nalgebra::{Point3, Rotation3, Vector3};
+
+// Below this, the xy-plane is taken to be seen edge-on
+const MIN_PLANE_TILT: f64 = 1e-3;
+
+// Direction the view is looking from. With both angles at zero the
+// xy-plane is seen from above, with x to the right and y down the screen,
+// which is how 2D setups have always been shown
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Camera {
+    pub yaw: f64, // Turn about the global z-axis, in radians
+    pub pitch: f64 // Tilt about the horizontal axis of the screen, in radians
+}
+
+impl Camera {
+    // Rotation from global axes to view axes, which are x to the right, y
+    // down the screen and z into it
+    pub fn rotation(&self) -> Rotation3<f64> {
+        Rotation3::from_axis_angle(&Vector3::x_axis(), self.pitch)
+            * Rotation3::from_axis_angle(&Vector3::z_axis(), self.yaw)
+    }
+
+    // Offset `d` from the point looked at, in view axes. The view is
+    // orthographic, so x and y are the offset on screen and z is the depth
+    pub fn to_view(self, d: &Vector3<f64>) -> Vector3<f64> {
+        self.rotation() * d
+    }
+
+    // Global point shown at on-screen offset (`x`, `y`) from `origin`, the
+    // point looked at. It's taken on the xy-plane, where 2D setups lie,
+    // unless that is seen edge-on, in which case it's taken at the depth of
+    // `origin` instead
+    pub fn to_global(self, origin: &Point3<f64>, x: f64, y: f64) -> Point3<f64> {
+        let inverse = self.rotation().inverse();
+        let on_screen = origin + inverse * Vector3::new(x, y, 0.0);
+        let forward = inverse * Vector3::z();
+        if forward.z.abs() < MIN_PLANE_TILT { return on_screen; }
+
+        on_screen - on_screen.z / forward.z * forward
+    }
+}
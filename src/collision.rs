@@ -0,0 +1,341 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+use crate::body::Body;
+
+// What happens when two bodies touch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionMode {
+    Off, // Bodies pass through each other
+    Merge, // Touching bodies stick together into one
+    Bounce // Touching bodies bounce off each other
+}
+
+impl CollisionMode {
+    pub const ALL: [CollisionMode; 3] = [
+        CollisionMode::Off,
+        CollisionMode::Merge,
+        CollisionMode::Bounce
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionMode::Off => "Off",
+            CollisionMode::Merge => "Merge",
+            CollisionMode::Bounce => "Bounce"
+        }
+    }
+}
+
+// Bounces are resolved one contact at a time, so a cluster of touching
+// bodies needs a few passes to settle
+const BOUNCE_PASSES: u32 = 8;
+
+// Pairs (i, j) with i < j whose x intervals `(start, end)` overlap,
+// found by sweeping along the x axis
+fn sweep(intervals: &[(f64, f64)]) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..intervals.len()).collect();
+    order.sort_by(|&i, &j| {
+        intervals[i].0.partial_cmp(&intervals[j].0).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut pairs = Vec::new();
+    for (k, &i) in order.iter().enumerate() {
+        for &j in &order[k+1..] {
+            if intervals[j].0 > intervals[i].1 { break; }
+            pairs.push((i.min(j), i.max(j)));
+        }
+    }
+    pairs
+}
+
+// Pairs of bodies (i, j) with i < j whose spheres overlap
+pub fn overlapping_pairs(bodies: &[Body]) -> Vec<(usize, usize)> {
+    let intervals: Vec<_> = bodies.iter()
+        .map(|b| (b.pos.x - b.radius, b.pos.x + b.radius))
+        .collect();
+
+    sweep(&intervals).into_iter()
+        .filter(|&(i, j)| {
+            let r = bodies[i].radius + bodies[j].radius;
+            (bodies[j].pos - bodies[i].pos).norm_squared() < r * r
+        })
+        .collect()
+}
+
+fn find(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root { root = parent[root]; }
+
+    let mut i = i;
+    while parent[i] != root {
+        let next = parent[i];
+        parent[i] = root;
+        i = next;
+    }
+    root
+}
+
+// Pairs of bodies (i, j) with i < j that touched at some point while
+// moving in straight lines from `start` to where they are now
+fn colliding_pairs(bodies: &[Body], start: &[Point3<f64>]) -> Vec<(usize, usize)> {
+    let intervals: Vec<_> = bodies.iter().zip(start)
+        .map(|(b, p)| (b.pos.x.min(p.x) - b.radius, b.pos.x.max(p.x) + b.radius))
+        .collect();
+
+    sweep(&intervals).into_iter()
+        .filter(|&(i, j)| {
+            let r = bodies[i].radius + bodies[j].radius;
+            time_of_impact(start[j] - start[i], bodies[j].pos - bodies[i].pos, r).is_some()
+        })
+        .collect()
+}
+
+// Merge every group of bodies that touched during the last step, given
+// where they were at its start, into a single body, conserving mass and
+// momentum. Checking along the paths rather than only at the end means
+// fast bodies (e.g. a satellite whose orbit decayed) can't skip past each
+// other. The merged body takes the place of the most massive member of
+// the group. If anything merged, returns where each old index ended up,
+// so callers can keep track of bodies (e.g. the selection)
+pub fn merge_colliding(bodies: &mut Vec<Body>, start: &[Point3<f64>]) -> Option<Vec<usize>> {
+    let n = bodies.len();
+    let pairs = colliding_pairs(bodies, start);
+    if pairs.is_empty() { return None; }
+
+    let mut parent: Vec<usize> = (0..n).collect();
+    for (i, j) in pairs {
+        let (root_i, root_j) = (find(&mut parent, i), find(&mut parent, j));
+        if root_i == root_j { continue; }
+
+        // Keep the heavier body as the root of the group
+        if bodies[root_i].mass >= bodies[root_j].mass {
+            parent[root_j] = root_i;
+        }
+        else {
+            parent[root_i] = root_j;
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new(); n];
+    for i in 0..n {
+        let root = find(&mut parent, i);
+        groups[root].push(i);
+    }
+
+    let mut merged = Vec::with_capacity(n);
+    let mut new_idx = vec![0; n];
+    for (root, group) in groups.iter().enumerate() {
+        if group.is_empty() { continue; }
+        for &i in group {
+            new_idx[i] = merged.len();
+        }
+        merged.push(merge(bodies, root, group));
+    }
+
+    *bodies = merged;
+    Some(new_idx)
+}
+
+fn merge(bodies: &[Body], root: usize, group: &[usize]) -> Body {
+    let mut result = bodies[root].clone();
+    if group.len() == 1 { return result; }
+
+    let mass: f64 = group.iter().map(|&i| bodies[i].mass).sum();
+    // Massless bodies are weighted equally so their average is still sensible
+    let weight = |i: usize| {
+        if mass > 0.0 { bodies[i].mass / mass } else { 1.0 / group.len() as f64 }
+    };
+
+    let mut pos = Vector3::new(0.0, 0.0, 0.0);
+    let mut v = Vector3::new(0.0, 0.0, 0.0);
+    let mut volume = 0.0;
+    let mut beta = 0.0;
+    let mut color = [0.0; 4];
+    for &i in group {
+        let w = weight(i);
+        pos += w * bodies[i].pos.coords;
+        v += w * bodies[i].v;
+        volume += bodies[i].radius.powi(3);
+        beta += w * bodies[i].beta;
+        for (c, c_) in color.iter_mut().zip(bodies[i].color.iter()) {
+            *c += w as f32 * c_;
+        }
+    }
+
+    result.mass = mass;
+    result.pos = Point3::from(pos);
+    result.v = v;
+    result.radius = volume.cbrt();
+    result.luminosity = group.iter().map(|&i| bodies[i].luminosity).sum();
+    result.beta = beta;
+    result.color = color;
+    result.test_particle = group.iter().all(|&i| bodies[i].test_particle);
+
+    // A pinned body holds whatever it merges with in place
+    if let Some(&i) = group.iter().find(|&&i| bodies[i].pinned) {
+        result.pos = bodies[i].pos;
+        result.v = Vector3::new(0.0, 0.0, 0.0);
+        result.pinned = true;
+    }
+    result
+}
+
+// Fractions of a collision's momentum change taken up by each body. The
+// lighter body takes the larger share, and pinned bodies take none
+fn shares(b: &Body, b_: &Body) -> (f64, f64) {
+    match (b.pinned, b_.pinned) {
+        (true, true) => (0.0, 0.0),
+        (true, false) => (0.0, 1.0),
+        (false, true) => (1.0, 0.0),
+        (false, false) => {
+            let mass = b.mass + b_.mass;
+            if mass > 0.0 { (b_.mass / mass, b.mass / mass) } else { (0.5, 0.5) }
+        }
+    }
+}
+
+// Earliest fraction of the step in [0, 1] at which two spheres moving
+// in straight lines from `d0` to `d1` apart touch, if they do at all
+fn time_of_impact(d0: Vector3<f64>, d1: Vector3<f64>, r: f64) -> Option<f64> {
+    let c = d0.norm_squared() - r * r;
+    if c < 0.0 { return Some(0.0); }
+
+    let delta = d1 - d0;
+    let a = delta.norm_squared();
+    if a == 0.0 { return None; }
+
+    let b = 2.0 * d0.dot(&delta);
+    let disc = b * b - 4.0 * a * c;
+    if disc < 0.0 { return None; }
+
+    let t = (-b - disc.sqrt()) / (2.0 * a);
+    if (0.0..=1.0).contains(&t) { Some(t) } else { None }
+}
+
+// Make bodies that touched during the last step of length `dt` bounce
+// off each other, given where they were at the start of the step.
+// Contacts are found along each body's path during the step rather than
+// only at the end, so fast bodies can't tunnel through each other.
+// `restitution` is 1 for perfectly elastic bounces and 0 for bodies that
+// come to rest against each other
+pub fn bounce(bodies: &mut [Body], start: &[Point3<f64>], dt: f64, restitution: f64) {
+    for _ in 0..BOUNCE_PASSES {
+        let intervals: Vec<_> = bodies.iter().zip(start)
+            .map(|(b, p)| (b.pos.x.min(p.x) - b.radius, b.pos.x.max(p.x) + b.radius))
+            .collect();
+
+        let mut contacts: Vec<(f64, usize, usize)> = sweep(&intervals).into_iter()
+            .filter_map(|(i, j)| {
+                let r = bodies[i].radius + bodies[j].radius;
+                let d0 = start[j] - start[i];
+                let d1 = bodies[j].pos - bodies[i].pos;
+                time_of_impact(d0, d1, r).map(|t| (t, i, j))
+            })
+            .collect();
+        contacts.sort_by(|c, c_| c.0.partial_cmp(&c_.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        let mut resolved = false;
+        for (t, i, j) in contacts {
+            let pos_i = start[i] + t * (bodies[i].pos - start[i]);
+            let pos_j = start[j] + t * (bodies[j].pos - start[j]);
+            let d = pos_j - pos_i;
+            if d.norm_squared() == 0.0 { continue; }
+
+            // Normal velocity along `n` as seen with time running the way
+            // the step does, so bodies approach when it's negative even if
+            // the simulation is reversed
+            let n = d.normalize();
+            let direction = dt.signum();
+            let vn = (bodies[j].v - bodies[i].v).dot(&n) * direction;
+            // Already separating, e.g. after an earlier contact this pass
+            if vn >= 0.0 { continue; }
+
+            let (share_i, share_j) = shares(&bodies[i], &bodies[j]);
+            bodies[i].v += (1.0 + restitution) * vn * direction * share_i * n;
+            bodies[j].v -= (1.0 + restitution) * vn * direction * share_j * n;
+
+            // Redo the rest of the step from the point of contact
+            bodies[i].pos = pos_i + (1.0 - t) * dt * bodies[i].v;
+            bodies[j].pos = pos_j + (1.0 - t) * dt * bodies[j].v;
+            resolved = true;
+        }
+
+        if !resolved { break; }
+    }
+
+    separate(bodies);
+}
+
+// Push apart bodies that still overlap (e.g. resting on each other),
+// moving the lighter body further
+fn separate(bodies: &mut [Body]) {
+    for _ in 0..BOUNCE_PASSES {
+        let pairs = overlapping_pairs(bodies);
+        if pairs.is_empty() { break; }
+
+        for (i, j) in pairs {
+            let d = bodies[j].pos - bodies[i].pos;
+            let dist = d.norm();
+            let depth = bodies[i].radius + bodies[j].radius - dist;
+            if dist == 0.0 || depth <= 0.0 { continue; }
+
+            let n = d / dist;
+            let (share_i, share_j) = shares(&bodies[i], &bodies[j]);
+            bodies[i].pos -= depth * share_i * n;
+            bodies[j].pos += depth * share_j * n;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn momentum(bodies: &[Body]) -> Vector3<f64> {
+        bodies.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.v)
+    }
+
+    // Move bodies in a straight line for `dt` seconds, returning where they
+    // started
+    fn drift(bodies: &mut [Body], dt: f64) -> Vec<Point3<f64>> {
+        let start = bodies.iter().map(|b| b.pos).collect();
+        for b in bodies.iter_mut() {
+            b.pos += dt * b.v;
+        }
+        start
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let mut bodies = vec![
+            Body::new(3.0, 1.0, Point3::new(0.0, 0.0, 0.0), Vector3::new(10.0, 1.0, 0.0)),
+            Body::new(1.0, 1.0, Point3::new(5.0, 0.5, 0.0), Vector3::new(-10.0, 0.0, 2.0)),
+            Body::new(1.0, 1.0, Point3::new(50.0, 50.0, 0.0), Vector3::new(0.0, 0.0, 0.0))
+        ];
+        let p = momentum(&bodies);
+        // The first two pass through each other within the step
+        let start = drift(&mut bodies, 1.0);
+
+        assert_eq!(merge_colliding(&mut bodies, &start), Some(vec![0, 0, 1]));
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(bodies[0].mass, 4.0);
+        assert!((momentum(&bodies) - p).norm() < 1e-12 * p.norm());
+    }
+
+    #[test]
+    fn bounce_when_reversed() {
+        for &dt in [1.0, -1.0].iter() {
+            // Approaching each other with time running either way
+            let mut bodies = vec![
+                Body::new(1.0, 1.0, Point3::new(0.0, 0.0, 0.0), Vector3::new(10.0 * dt, 0.0, 0.0)),
+                Body::new(1.0, 1.0, Point3::new(5.0, 0.0, 0.0), Vector3::new(-10.0 * dt, 0.0, 0.0))
+            ];
+            let start = drift(&mut bodies, dt);
+            bounce(&mut bodies, &start, dt, 1.0);
+
+            assert_eq!(bodies[0].v.x, -10.0 * dt);
+            assert_eq!(bodies[1].v.x, 10.0 * dt);
+            assert!(bodies[1].pos.x - bodies[0].pos.x >= 2.0);
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use ggez::nalgebra::Vector3;
+
+use crate::body::{Body, G};
+use crate::forces::{Forces, par_map, sources};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ForceKind {
+    Pairwise, // Acts between every pair of bodies
+    External // Acts on each body on its own, e.g. a background field or drag
+}
+
+// A law contributing to the acceleration of bodies. Implementing `pairwise`
+// or `external` (depending on `kind`) is enough, and the sum over bodies is
+// done in parallel by `add_accels`. Test particles are never the source of
+// a pairwise force, and in a periodic domain `pairwise` is handed the
+// separation to the nearest image of the other body
+pub trait ForceModel: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn kind(&self) -> ForceKind;
+
+    // Whether the model currently does anything to `bodies` at all, so it
+    // can be skipped otherwise
+    fn is_active(&self, _bodies: &[Body], _forces: &Forces) -> bool { true }
+
+    // Acceleration of `body` due to `other`, `d` away from it, for
+    // pairwise models
+    fn pairwise(&self, _body: &Body, _other: &Body, _d: &Vector3<f64>,
+                _forces: &Forces) -> Vector3<f64> {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+
+    // Acceleration of `body` due to the field, for external models
+    fn external(&self, _body: &Body, _forces: &Forces) -> Vector3<f64> {
+        Vector3::new(0.0, 0.0, 0.0)
+    }
+
+    // Add this model's acceleration to that of every body. Can be
+    // overridden with something faster than summing over all pairs
+    fn add_accels(&self, bodies: &mut [Body], forces: &Forces) {
+        let accels = match self.kind() {
+            ForceKind::Pairwise => {
+                let sources = sources(bodies);
+                par_map(bodies, forces.threads, |bodies, idx| {
+                    let mut a = Vector3::new(0.0, 0.0, 0.0);
+                    for &idx_ in &sources {
+                        if idx_ != idx {
+                            let (b, other) = (&bodies[idx], &bodies[idx_]);
+                            a += self.pairwise(b, other, &forces.separation(b, other), forces);
+                        }
+                    }
+                    a
+                })
+            }
+            ForceKind::External => {
+                par_map(bodies, forces.threads, |bodies, idx| self.external(&bodies[idx], forces))
+            }
+        };
+
+        for (b, a) in bodies.iter_mut().zip(accels) {
+            b.a += a;
+        }
+    }
+
+    // Add this model's acceleration and jerk to those of every body. Models
+    // whose jerk is negligible can leave it out
+    fn add_accels_and_jerks(&self, bodies: &mut [Body], forces: &Forces) {
+        self.add_accels(bodies, forces);
+    }
+}
+
+// Newtonian gravity, summed with the chosen solver
+pub struct Gravity;
+
+impl ForceModel for Gravity {
+    fn name(&self) -> &'static str { "Gravity" }
+
+    fn kind(&self) -> ForceKind { ForceKind::Pairwise }
+
+    fn pairwise(&self, body: &Body, other: &Body, d: &Vector3<f64>,
+                forces: &Forces) -> Vector3<f64> {
+        body.accel_towards(other, d, forces.softening)
+    }
+
+    fn add_accels(&self, bodies: &mut [Body], forces: &Forces) {
+        let accels = forces.gravity(bodies);
+        for (b, a) in bodies.iter_mut().zip(accels) {
+            b.a += a;
+        }
+    }
+
+    // Jerks are only needed by Hermite, which always sums over all pairs
+    // directly. The Ewald correction of a periodic domain changes slowly, so
+    // it's left out of the jerk
+    fn add_accels_and_jerks(&self, bodies: &mut [Body], forces: &Forces) {
+        let softening = forces.softening;
+        let sources = sources(bodies);
+        let results = par_map(bodies, forces.threads, |bodies, idx| {
+            let b = &bodies[idx];
+            let (mut a, mut jerk) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+            for &idx_ in &sources {
+                if idx_ != idx {
+                    let other = &bodies[idx_];
+                    let d = forces.separation(b, other);
+                    let (a_, jerk_) = b.accel_jerk_towards(other, &d, softening);
+                    a += a_;
+                    jerk += jerk_;
+                    if let Some(periodic) = &forces.periodic {
+                        a += G * other.mass * periodic.ewald_accel(&-d);
+                    }
+                }
+            }
+            (a, jerk)
+        });
+
+        for (b, (a, jerk)) in bodies.iter_mut().zip(results) {
+            b.a += a;
+            b.jerk += jerk;
+        }
+    }
+}
+
+// First post-Newtonian correction to gravity, always summed directly.
+// Too small to matter for the jerk
+pub struct Relativity;
+
+impl ForceModel for Relativity {
+    fn name(&self) -> &'static str { "Relativity (1PN)" }
+
+    fn kind(&self) -> ForceKind { ForceKind::Pairwise }
+
+    fn pairwise(&self, body: &Body, other: &Body, d: &Vector3<f64>,
+                _forces: &Forces) -> Vector3<f64> {
+        if other.mass > 0.0 { body.relativistic_accel_towards(other, d) }
+        else { Vector3::new(0.0, 0.0, 0.0) }
+    }
+}
+
+// Pull of the background potentials on every body
+pub struct Background;
+
+impl ForceModel for Background {
+    fn name(&self) -> &'static str { "Background potentials" }
+
+    fn kind(&self) -> ForceKind { ForceKind::External }
+
+    fn is_active(&self, _bodies: &[Body], forces: &Forces) -> bool {
+        !forces.potentials.is_empty()
+    }
+
+    fn external(&self, body: &Body, forces: &Forces) -> Vector3<f64> {
+        forces.potentials.iter().fold(Vector3::new(0.0, 0.0, 0.0), |a, p| a + p.accel(&body.pos))
+    }
+}
+
+// Push of light from luminous bodies on particles with a nonzero beta,
+// along with the Poynting-Robertson drag that makes dust spiral inwards.
+// The bodies giving off the light don't feel anything in return
+pub struct Radiation;
+
+impl ForceModel for Radiation {
+    fn name(&self) -> &'static str { "Radiation pressure" }
+
+    fn kind(&self) -> ForceKind { ForceKind::Pairwise }
+
+    fn is_active(&self, bodies: &[Body], _forces: &Forces) -> bool {
+        bodies.iter().any(|b| b.luminosity > 0.0) && bodies.iter().any(|b| b.beta != 0.0)
+    }
+
+    fn pairwise(&self, body: &Body, other: &Body, d: &Vector3<f64>,
+                _forces: &Forces) -> Vector3<f64> {
+        body.radiation_accel_jerk(other, d).0
+    }
+
+    // Dust can have beta close to 1, so unlike with relativity the jerk
+    // matters to Hermite
+    fn add_accels_and_jerks(&self, bodies: &mut [Body], forces: &Forces) {
+        let sources = sources(bodies);
+        let results = par_map(bodies, forces.threads, |bodies, idx| {
+            let (mut a, mut jerk) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+            for &idx_ in &sources {
+                if idx_ != idx {
+                    let (b, other) = (&bodies[idx], &bodies[idx_]);
+                    let (a_, jerk_) = b.radiation_accel_jerk(other, &forces.separation(b, other));
+                    a += a_;
+                    jerk += jerk_;
+                }
+            }
+            (a, jerk)
+        });
+
+        for (b, (a, jerk)) in bodies.iter_mut().zip(results) {
+            b.a += a;
+            b.jerk += jerk;
+        }
+    }
+}
+
+// Engines of spacecraft in the middle of a burn. The direction of each
+// burn is relative to the body pulling hardest on the spacecraft
+pub struct Thrust;
+
+impl ForceModel for Thrust {
+    fn name(&self) -> &'static str { "Spacecraft thrust" }
+
+    fn kind(&self) -> ForceKind { ForceKind::External }
+
+    fn is_active(&self, bodies: &[Body], _forces: &Forces) -> bool {
+        bodies.iter().any(|b| b.spacecraft.as_ref().is_some_and(|s| s.burn.is_some()))
+    }
+
+    fn add_accels(&self, bodies: &mut [Body], forces: &Forces) {
+        let sources = sources(bodies);
+        let accels: Vec<_> = bodies.iter()
+            .map(|b| {
+                if b.spacecraft.is_none() { return Vector3::new(0.0, 0.0, 0.0); }
+
+                // Pull of each other source, up to a factor of G
+                let pull = |(other, d): &(&Body, Vector3<f64>)| other.mass / d.norm_squared();
+                let primary = sources.iter()
+                    .map(|&idx| (&bodies[idx], forces.separation(b, &bodies[idx])))
+                    .filter(|(_, d)| d.norm_squared() > 0.0)
+                    .max_by(|other, other_| {
+                        pull(other).partial_cmp(&pull(other_)).unwrap_or(std::cmp::Ordering::Equal)
+                    });
+                b.thrust_accel(primary)
+            })
+            .collect();
+
+        for (b, a) in bodies.iter_mut().zip(accels) {
+            b.a += a;
+        }
+    }
+}
+
+// Drag on bodies passing through atmospheres, along with the equal and
+// opposite push on the body each atmosphere belongs to
+pub struct Drag;
+
+impl ForceModel for Drag {
+    fn name(&self) -> &'static str { "Atmospheric drag" }
+
+    fn kind(&self) -> ForceKind { ForceKind::Pairwise }
+
+    fn is_active(&self, bodies: &[Body], _forces: &Forces) -> bool {
+        bodies.iter().any(|b| b.atmosphere.is_some())
+    }
+
+    fn pairwise(&self, body: &Body, other: &Body, d: &Vector3<f64>,
+                _forces: &Forces) -> Vector3<f64> {
+        let mut a = body.drag_accel(other, d);
+        if body.mass > 0.0 {
+            a -= other.mass / body.mass * other.drag_accel(body, &-d);
+        }
+        a
+    }
+}
@@ -0,0 +1,277 @@
+use std::thread;
+
+use ggez::nalgebra::Vector3;
+
+use crate::body::{Body, G, point_mass_accel};
+use crate::octree::Octree;
+use crate::force_model::{ForceModel, ForceKind, Gravity, Relativity, Background, Radiation,
+                         Thrust, Drag};
+use crate::potential::Potential;
+use crate::periodic::PeriodicBox;
+
+// Method used to sum up the gravitational pull on each body
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Solver {
+    Direct, // Exact pairwise sum, O(n^2)
+    BarnesHut // Octree approximation, O(n log n)
+}
+
+impl Solver {
+    pub const ALL: [Solver; 2] = [Solver::Direct, Solver::BarnesHut];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Solver::Direct => "Direct sum",
+            Solver::BarnesHut => "Barnes-Hut"
+        }
+    }
+}
+
+pub struct Forces {
+    pub solver: Solver, // Used for Newtonian gravity
+    pub theta: f64, // Opening angle for Barnes-Hut, 0 gives the exact sum
+    pub softening: f64, // Plummer softening length, applied as r^2 + eps^2
+    pub threads: usize, // Number of threads to split the force pass over
+    pub models: Vec<(Box<dyn ForceModel>, bool)>, // Force laws, and whether each is enabled
+    pub potentials: Vec<Potential>, // Fixed background fields
+    pub periodic: Option<PeriodicBox> // Domain the bodies wrap around in, if any
+}
+
+impl Default for Forces {
+    fn default() -> Forces {
+        Forces {
+            solver: Solver::Direct,
+            theta: 0.5,
+            softening: 0.0,
+            threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            models: vec![
+                (Box::new(Gravity), true),
+                (Box::new(Relativity), false),
+                (Box::new(Background), true),
+                (Box::new(Radiation), true),
+                (Box::new(Thrust), true),
+                (Box::new(Drag), true)
+            ],
+            potentials: Vec::new(),
+            periodic: None
+        }
+    }
+}
+
+// Below this many bodies, starting threads costs more than it saves
+const MIN_BODIES_PER_THREAD: usize = 64;
+
+impl Forces {
+    // Models that are turned on, whether or not they do anything
+    pub fn enabled_models(&self) -> impl Iterator<Item = &dyn ForceModel> {
+        self.models.iter().filter(|(_, enabled)| *enabled).map(|(model, _)| model.as_ref())
+    }
+
+    // Enabled models that currently do something to `bodies`
+    fn active_models(&self, bodies: &[Body]) -> Vec<&dyn ForceModel> {
+        self.enabled_models().filter(|model| model.is_active(bodies, self)).collect()
+    }
+
+    // Whether any active model acts on `bodies` from outside
+    pub fn has_external(&self, bodies: &[Body]) -> bool {
+        self.active_models(bodies).iter().any(|model| model.kind() == ForceKind::External)
+    }
+
+    // Recompute the acceleration of every body from all enabled models
+    pub fn update_accels(&self, bodies: &mut [Body]) {
+        for b in bodies.iter_mut() {
+            b.a = Vector3::new(0.0, 0.0, 0.0);
+        }
+        for model in self.active_models(bodies) {
+            model.add_accels(bodies, self);
+        }
+        hold_pinned(bodies);
+    }
+
+    // Recompute both the acceleration and jerk of every body. Jerks are only
+    // needed by Hermite, and models that don't provide them only add to the
+    // acceleration
+    pub fn update_accels_and_jerks(&self, bodies: &mut [Body]) {
+        for b in bodies.iter_mut() {
+            b.a = Vector3::new(0.0, 0.0, 0.0);
+            b.jerk = Vector3::new(0.0, 0.0, 0.0);
+        }
+        for model in self.active_models(bodies) {
+            model.add_accels_and_jerks(bodies, self);
+        }
+        hold_pinned(bodies);
+    }
+
+    // Separation from `body` to `other`, or to the image of `other` nearest
+    // to it if the domain is periodic, so pairwise forces only need to look
+    // at one copy of it
+    pub fn separation(&self, body: &Body, other: &Body) -> Vector3<f64> {
+        let d = other.pos - body.pos;
+        match &self.periodic {
+            Some(periodic) => periodic.nearest_image(&d),
+            None => d
+        }
+    }
+
+    // Pull of `other` on `body` from all enabled pairwise models
+    pub fn pair_accel(&self, body: &Body, other: &Body) -> Vector3<f64> {
+        let d = self.separation(body, other);
+        self.enabled_models()
+            .filter(|model| model.kind() == ForceKind::Pairwise)
+            .fold(Vector3::new(0.0, 0.0, 0.0), |a, model| a + model.pairwise(body, other, &d, self))
+    }
+
+    // Mean relative difference between the gravitational accelerations
+    // given by the current solver and the exact ones, to judge the choice
+    // of `theta`
+    pub fn relative_error(&self, bodies: &[Body]) -> f64 {
+        if bodies.is_empty() { return 0.0; }
+
+        let approx = self.gravity(bodies);
+        let exact = match &self.periodic {
+            Some(periodic) => periodic_sum(bodies, periodic, self.softening, self.threads),
+            None => direct_sum(bodies, self.softening, self.threads)
+        };
+
+        let mut sum = 0.0;
+        for (a, a_) in approx.iter().zip(exact.iter()) {
+            let norm = a_.norm();
+            if norm > 0.0 {
+                sum += (a - a_).norm() / norm;
+            }
+        }
+        sum / bodies.len() as f64
+    }
+
+    // Newtonian acceleration of every body, using the current solver.
+    // Barnes-Hut doesn't know about images, so periodic domains are always
+    // summed directly
+    pub fn gravity(&self, bodies: &[Body]) -> Vec<Vector3<f64>> {
+        if let Some(periodic) = &self.periodic {
+            return periodic_sum(bodies, periodic, self.softening, self.threads);
+        }
+        match self.solver {
+            Solver::Direct => direct_sum(bodies, self.softening, self.threads),
+            Solver::BarnesHut => barnes_hut(bodies, self.theta, self.softening, self.threads)
+        }
+    }
+}
+
+// Compute `f(bodies, idx)` for every body, with the bodies split into
+// contiguous chunks across `threads` threads. Each body's result is summed
+// on its own in a fixed order, so the results don't depend on the number
+// of threads
+pub fn par_map<T, F>(bodies: &[Body], threads: usize, f: F) -> Vec<T>
+    where T: Send, F: Fn(&[Body], usize) -> T + Sync {
+    let n = bodies.len();
+    let threads = threads.min(n / MIN_BODIES_PER_THREAD).max(1);
+    if threads == 1 {
+        return (0..n).map(|idx| f(bodies, idx)).collect();
+    }
+
+    let mut results: Vec<Option<T>> = (0..n).map(|_| None).collect();
+    let chunk_size = n.div_ceil(threads);
+    let f = &f;
+    thread::scope(|s| {
+        for (chunk, out) in results.chunks_mut(chunk_size).enumerate() {
+            s.spawn(move || {
+                for (k, r) in out.iter_mut().enumerate() {
+                    *r = Some(f(bodies, chunk * chunk_size + k));
+                }
+            });
+        }
+    });
+    results.into_iter().map(|r| r.unwrap()).collect()
+}
+
+// Indices of the bodies that pull on others, i.e. everything except test
+// particles. Summing over these only keeps the cost at O(n * sources)
+pub fn sources(bodies: &[Body]) -> Vec<usize> {
+    bodies.iter().enumerate()
+        .filter(|(_, b)| !b.test_particle)
+        .map(|(idx, _)| idx)
+        .collect()
+}
+
+fn direct_sum(bodies: &[Body], softening: f64, threads: usize) -> Vec<Vector3<f64>> {
+    let sources = sources(bodies);
+    par_map(bodies, threads, |bodies, idx| {
+        let b = &bodies[idx];
+        let mut a = Vector3::new(0.0, 0.0, 0.0);
+        for &idx_ in &sources {
+            if idx_ != idx {
+                let other = &bodies[idx_];
+                a += b.accel_towards(other, &(other.pos - b.pos), softening);
+            }
+        }
+        a
+    })
+}
+
+// Direct sum over the nearest image of each body, plus the Ewald
+// correction for the rest
+fn periodic_sum(bodies: &[Body], periodic: &PeriodicBox, softening: f64,
+                threads: usize) -> Vec<Vector3<f64>> {
+    let sources = sources(bodies);
+    par_map(bodies, threads, |bodies, idx| {
+        let b = &bodies[idx];
+        let mut a = Vector3::new(0.0, 0.0, 0.0);
+        for &idx_ in &sources {
+            if idx_ != idx {
+                let other = &bodies[idx_];
+                let d = periodic.nearest_image(&(other.pos - b.pos));
+                let other_eps = other.softening.unwrap_or(softening);
+                let eps_squared = b.pair_softening(softening, other_eps * other_eps);
+                a += point_mass_accel(&d, other.mass, eps_squared);
+                a += G * other.mass * periodic.ewald_accel(&-d);
+            }
+        }
+        a
+    })
+}
+
+fn barnes_hut(bodies: &[Body], theta: f64, softening: f64,
+              threads: usize) -> Vec<Vector3<f64>> {
+    let tree = Octree::new(bodies);
+    par_map(bodies, threads, |bodies, idx| tree.accel_on(bodies, idx, theta, softening))
+}
+
+// Pinned bodies stay where they are, however hard they're pulled
+fn hold_pinned(bodies: &mut [Body]) {
+    for b in bodies.iter_mut().filter(|b| b.pinned) {
+        b.a = Vector3::new(0.0, 0.0, 0.0);
+        b.jerk = Vector3::new(0.0, 0.0, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ggez::nalgebra::Point3;
+
+    use super::*;
+
+    // Bodies scattered through a cube by a fixed pseudo-random sequence
+    fn scattered(n: usize) -> Vec<Body> {
+        let mut seed: u64 = 12345;
+        let mut next = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+        };
+        (0..n).map(|_| {
+            let pos = Point3::new(next(), next(), next()) * 1e12;
+            Body::new(1e24 * (1.0 + next()), 0.0, pos, Vector3::new(0.0, 0.0, 0.0))
+        }).collect()
+    }
+
+    #[test]
+    fn same_result_on_any_number_of_threads() {
+        // Enough bodies for every thread to get a chunk
+        let bodies = scattered(MIN_BODIES_PER_THREAD * 7 + 50);
+        let direct = direct_sum(&bodies, 1e6, 1);
+        let tree = barnes_hut(&bodies, 0.5, 1e6, 1);
+        for &threads in [2, 7].iter() {
+            assert_eq!(direct_sum(&bodies, 1e6, threads), direct);
+            assert_eq!(barnes_hut(&bodies, 0.5, 1e6, threads), tree);
+        }
+    }
+}
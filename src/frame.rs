@@ -0,0 +1,93 @@
+use ggez::nalgebra::{Matrix3, Point3, Rotation3, Vector3};
+
+use crate::body::Body;
+
+// Reference frame the bodies are shown in. The simulation itself always
+// runs in the inertial frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    Inertial,
+    // Turning with two bodies about their barycentre, with the x-axis along
+    // the line from the first to the second and the z-axis along their
+    // angular momentum, so Lagrange points stay put
+    CoRotating(usize, usize)
+}
+
+impl Frame {
+    pub fn name(&self) -> String {
+        match self {
+            Frame::Inertial => "Inertial".to_string(),
+            Frame::CoRotating(first, second) => format!("Co-rotating #{}-#{}", first, second)
+        }
+    }
+
+    // Where the frame is at the moment. Falls back to the inertial frame if
+    // either body is gone or the two are in the same place
+    pub fn transform(&self, bodies: &[Body]) -> FrameTransform {
+        let (first, second) = match *self {
+            Frame::CoRotating(first, second) if first < bodies.len() && second < bodies.len() => {
+                (&bodies[first], &bodies[second])
+            }
+            _ => return FrameTransform::identity()
+        };
+
+        let r = second.pos - first.pos;
+        if r.norm_squared() == 0.0 { return FrameTransform::identity(); }
+
+        let total_mass = first.mass + second.mass;
+        let center = if total_mass > 0.0 {
+            Point3::from((first.mass * first.pos.coords + second.mass * second.pos.coords)
+                         / total_mass)
+        } else {
+            Point3::from((first.pos.coords + second.pos.coords) / 2.0)
+        };
+
+        // Bodies moving straight towards or away from each other don't
+        // define a plane, so keep the z-axis as close to the global one as
+        // the x-axis allows
+        let x = r.normalize();
+        let normal = r.cross(&(second.v - first.v));
+        let z = if normal.norm_squared() > 0.0 { normal.normalize() } else {
+            let z = Vector3::z() - Vector3::z().dot(&x) * x;
+            if z.norm_squared() > 0.0 { z.normalize() } else { Vector3::y() }
+        };
+        let y = z.cross(&x);
+
+        // Columns are the frame's axes, so this takes frame to global
+        let axes = Rotation3::from_matrix_unchecked(Matrix3::from_columns(&[x, y, z]));
+        FrameTransform { center, rotation: axes.inverse() }
+    }
+
+    // The frame after bodies have moved around, given where each old index
+    // ended up. Becomes inertial if either body is gone or both became one
+    pub fn reindex<F: Fn(usize) -> Option<usize>>(&self, new_idx: F) -> Frame {
+        match *self {
+            Frame::CoRotating(first, second) => match (new_idx(first), new_idx(second)) {
+                (Some(first), Some(second)) if first != second => Frame::CoRotating(first, second),
+                _ => Frame::Inertial
+            },
+            Frame::Inertial => Frame::Inertial
+        }
+    }
+}
+
+// Rigid motion taking global positions to those in a frame
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTransform {
+    center: Point3<f64>, // Global position of the frame's origin
+    rotation: Rotation3<f64> // From global axes to the frame's
+}
+
+impl FrameTransform {
+    pub fn identity() -> FrameTransform {
+        FrameTransform { center: Point3::origin(), rotation: Rotation3::identity() }
+    }
+
+    pub fn to_frame(self, pos: &Point3<f64>) -> Point3<f64> {
+        Point3::from(self.rotation * (pos - self.center))
+    }
+
+    pub fn to_global(self, pos: &Point3<f64>) -> Point3<f64> {
+        self.center + self.rotation.inverse() * pos.coords
+    }
+}
@@ -0,0 +1,75 @@
+use ggez::nalgebra::Vector3;
+
+use crate::body::Body;
+use crate::forces::Forces;
+use crate::integrator::{adaptive_advance, Attempt, IntegratorState, MIN_FACTOR};
+
+// Accuracy parameter for the very first step, before snap and crackle
+// are known
+const ETA_START: f64 = 0.01;
+
+// Advance `bodies` by `duration` seconds (negative to go backwards) with
+// the 4th order Hermite predictor-corrector scheme. All bodies share one
+// timestep, the smallest given by Aarseth's criterion with accuracy
+// parameter `state.eta`
+pub fn advance(bodies: &mut [Body], duration: f64, state: &mut IntegratorState,
+               forces: &Forces) -> f64 {
+    if bodies.is_empty() { return duration; }
+    forces.update_accels_and_jerks(bodies);
+
+    // Don't trust the step carried over from the last frame more than what
+    // the current accelerations and jerks suggest, as bodies may have been
+    // edited in between
+    let h = initial_step(bodies);
+    state.h = if state.h > 0.0 { h.min(state.h) } else { h };
+    adaptive_advance(duration, state, |dt, state| {
+        let old = bodies.to_vec();
+        let next = step(bodies, dt, state.eta, forces);
+        // Undo and retry a step that was far longer than the criterion
+        // allows at its end, rather than only sizing the next one
+        let accepted = next >= MIN_FACTOR * dt.abs();
+        if !accepted { bodies.clone_from_slice(&old); }
+        Attempt { accepted, factor: next / dt.abs() }
+    })
+}
+
+fn initial_step(bodies: &[Body]) -> f64 {
+    bodies.iter()
+        .filter(|b| b.jerk.norm() > 0.0)
+        .map(|b| ETA_START * b.a.norm() / b.jerk.norm())
+        .fold(f64::INFINITY, f64::min)
+}
+
+// Take a single step of `dt` seconds, leaving accelerations and jerks at
+// the new positions in `bodies`. Returns the next step size
+fn step(bodies: &mut [Body], dt: f64, eta: f64, forces: &Forces) -> f64 {
+    let old = bodies.to_vec();
+
+    // Predict positions and velocities with a Taylor expansion
+    for b in bodies.iter_mut() {
+        b.pos += dt * b.v + dt * dt / 2.0 * b.a + dt * dt * dt / 6.0 * b.jerk;
+        b.v += dt * b.a + dt * dt / 2.0 * b.jerk;
+    }
+
+    forces.update_accels_and_jerks(bodies);
+
+    let mut next = f64::INFINITY;
+    for (b, b_) in bodies.iter_mut().zip(old.iter()) {
+        // Correct with the interpolating polynomial through both ends
+        b.v = b_.v + dt / 2.0 * (b_.a + b.a) + dt * dt / 12.0 * (b_.jerk - b.jerk);
+        b.pos = b_.pos + dt / 2.0 * (b_.v + b.v) + dt * dt / 12.0 * (b_.a - b.a);
+
+        // Snap and crackle at the end of the step, from the same polynomial
+        let snap_0: Vector3<f64> = (-6.0 * (b_.a - b.a) - dt * (4.0 * b_.jerk + 2.0 * b.jerk))
+            / (dt * dt);
+        let crackle = (12.0 * (b_.a - b.a) + 6.0 * dt * (b_.jerk + b.jerk)) / (dt * dt * dt);
+        let snap = snap_0 + dt * crackle;
+
+        let (a, j, s, c) = (b.a.norm(), b.jerk.norm(), snap.norm(), crackle.norm());
+        let denominator = j * c + s * s;
+        if denominator > 0.0 {
+            next = next.min((eta * (a * s + j * j) / denominator).sqrt());
+        }
+    }
+    next
+}
@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+use std::mem::size_of_val;
+
+use crate::body::Body;
+
+// Limit on the memory taken by the bodies across all snapshots, so long
+// runs with many bodies don't eat up all of it. The oldest snapshots are
+// dropped first
+const MAX_STORED_BYTES: usize = 256 * 1024 * 1024;
+
+// Steps recorded per snapshot. Only the time of the steps in between is
+// kept, and the bodies are integrated forward again from the snapshot to
+// retrace them
+const SNAPSHOT_INTERVAL: usize = 10;
+
+// Bodies at one recorded time, and the times of the steps recorded after
+// it up to the next snapshot. Each step keeps the settings `S` it was
+// taken with, so it can be redone exactly even if they changed since
+struct Snapshot<S> {
+    time: f64,
+    bodies: Vec<Body>,
+    settings: S,
+    later: Vec<(f64, S)>
+}
+
+// Record of the steps taken, along with the simulated time, so reversing
+// can retrace earlier states instead of integrating backwards
+pub struct History<S> {
+    snapshots: VecDeque<Snapshot<S>>,
+    stored: usize, // Bytes taken by the bodies across all snapshots
+    // Where the last recorded step left the bodies. If they don't match at
+    // the next step, they were edited and a new snapshot is needed
+    landed: Vec<Body>,
+    // Bodies at the latest times in `later` of the last snapshot, worked out
+    // again from the snapshot while reversing
+    replayed: Vec<(f64, Vec<Body>)>
+}
+
+impl<S> Default for History<S> {
+    fn default() -> History<S> {
+        History {
+            snapshots: VecDeque::new(),
+            stored: 0,
+            landed: Vec::new(),
+            replayed: Vec::new()
+        }
+    }
+}
+
+impl<S> History<S> {
+    // Record the bodies before a step, and the settings it is taken with
+    pub fn push(&mut self, time: f64, bodies: &[Body], settings: S) {
+        self.replayed.clear();
+        let edited = bodies != &self.landed[..];
+        match self.snapshots.back_mut() {
+            Some(last) if !edited && last.later.len() + 1 < SNAPSHOT_INTERVAL => {
+                last.later.push((time, settings));
+            }
+            _ => {
+                self.snapshots.push_back(Snapshot {
+                    time,
+                    bodies: bodies.to_vec(),
+                    settings,
+                    later: Vec::new()
+                });
+                self.stored += size_of_val(bodies);
+            }
+        }
+
+        while self.stored > MAX_STORED_BYTES && self.snapshots.len() > 1 {
+            if let Some(oldest) = self.snapshots.pop_front() {
+                self.stored -= size_of_val(&oldest.bodies[..]);
+            }
+        }
+    }
+
+    // Record the bodies after the step recorded last
+    pub fn landed(&mut self, bodies: &[Body]) {
+        self.landed.clear();
+        self.landed.extend_from_slice(bodies);
+    }
+
+    // The bodies before the step recorded last, along with the time. Steps
+    // since the last snapshot are redone with `replay`, which takes bodies
+    // from one time to another with the settings of that step
+    pub fn pop<F>(&mut self, mut replay: F) -> Option<(f64, Vec<Body>)>
+        where F: FnMut(&mut Vec<Body>, f64, f64, &S) {
+        let last = self.snapshots.back_mut()?;
+        if last.later.is_empty() {
+            let last = self.snapshots.pop_back()?;
+            self.stored -= size_of_val(&last.bodies[..]);
+            self.landed.clear();
+            return Some((last.time, last.bodies));
+        }
+
+        if self.replayed.is_empty() {
+            let (mut time, mut bodies) = (last.time, last.bodies.clone());
+            let mut settings = &last.settings;
+            for (t, next) in last.later.iter() {
+                replay(&mut bodies, time, *t, settings);
+                time = *t;
+                settings = next;
+                self.replayed.push((time, bodies.clone()));
+            }
+        }
+        last.later.pop();
+        self.landed.clear();
+        self.replayed.pop()
+    }
+
+    // Number of steps that can be retraced
+    pub fn len(&self) -> usize {
+        self.snapshots.iter().map(|s| 1 + s.later.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ggez::nalgebra::{Point3, Vector3};
+
+    use super::*;
+    use crate::forces::Forces;
+    use crate::integrator::{Integrator, IntegratorState};
+    use crate::integrator::tests::sun_earth;
+
+    // Stand-in for integrating: bodies just drift
+    fn drift(bodies: &mut [Body], from: f64, to: f64) {
+        for b in bodies.iter_mut() {
+            b.pos += (to - from) * b.v;
+        }
+    }
+
+    #[test]
+    fn pops_in_reverse_order() {
+        let mut history = History::default();
+        let mut bodies = vec![
+            Body::new(1.0, 1.0, Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 2.0, 0.0))
+        ];
+        let mut recorded = Vec::new();
+        for step in 0..3 * SNAPSHOT_INTERVAL + 4 {
+            let time = step as f64;
+            history.push(time, &bodies, ());
+            recorded.push((time, bodies.clone()));
+            drift(&mut bodies, time, time + 1.0);
+            history.landed(&bodies);
+        }
+        assert_eq!(history.len(), recorded.len());
+
+        while let Some(state) = history.pop(|bodies, from, to, _| drift(bodies, from, to)) {
+            assert_eq!(Some(state), recorded.pop());
+        }
+        assert!(recorded.is_empty());
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn keeps_edits_between_steps() {
+        let mut history = History::default();
+        let mut bodies = vec![
+            Body::new(1.0, 1.0, Point3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0))
+        ];
+        for step in 0..4 {
+            let time = step as f64;
+            // Nudge the body after the second step, which replaying from the
+            // first snapshot wouldn't know about
+            if step == 2 { bodies[0].v.y = 5.0; }
+            history.push(time, &bodies, ());
+            drift(&mut bodies, time, time + 1.0);
+            history.landed(&bodies);
+        }
+
+        let (time, bodies) = history.pop(|bodies, from, to, _| drift(bodies, from, to)).unwrap();
+        assert_eq!(time, 3.0);
+        assert_eq!(bodies[0].pos, Point3::new(3.0, 5.0, 0.0));
+    }
+
+    #[test]
+    fn replays_adaptive_steps_with_their_settings() {
+        let mut history = History::default();
+        let mut bodies = sun_earth();
+        let forces = Forces::default();
+        let mut state = IntegratorState::default();
+        let mut recorded = Vec::new();
+        for step in 0..SNAPSHOT_INTERVAL + 4 {
+            let time = step as f64 * 1e6;
+            // Tighten the tolerance partway, so redoing the earlier steps
+            // with the settings at the end would take different substeps
+            if step == 5 { state.rtol = 1e-9; }
+            history.push(time, &bodies, state.clone());
+            recorded.push((time, bodies.clone()));
+            Integrator::Rk45.advance(&mut bodies, 1e6, &mut state, &forces);
+            history.landed(&bodies);
+        }
+
+        let replay = |bodies: &mut Vec<Body>, from: f64, to: f64, settings: &IntegratorState| {
+            let mut state = settings.clone();
+            Integrator::Rk45.advance(bodies, to - from, &mut state, &forces);
+        };
+        while let Some(state) = history.pop(replay) {
+            assert_eq!(Some(state), recorded.pop());
+        }
+        assert!(recorded.is_empty());
+    }
+}
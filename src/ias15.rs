@@ -0,0 +1,267 @@
+use ggez::nalgebra::Vector3;
+
+use crate::body::Body;
+use crate::forces::Forces;
+use crate::integrator::{adaptive_advance, Attempt, IntegratorState};
+
+// Gauss-Radau spacings of the substeps, as fractions of the step
+const NODES: [f64; 8] = [
+    0.0,
+    0.05626256053692215,
+    0.18024069173689236,
+    0.3526247171131696,
+    0.5471536263305554,
+    0.7342101772154105,
+    0.8853209468390958,
+    0.9775206135612875
+];
+
+// Steps are (7! EPSILON)^(1/7) times the time scale on which the
+// acceleration of any body changes, so the truncation error stays below
+// double precision round-off
+const EPSILON: f64 = 1e-9;
+
+// Steps that would have to shrink more than this are redone
+const SAFETY: f64 = 0.25;
+
+// Predictor-corrector iterations per step, which usually converge in two
+const MAX_ITERATIONS: u32 = 12;
+
+// Acceleration over a step as a polynomial a(t) = a0 + b0 t + ... + b6 t^7,
+// with t the fraction of the step
+type Coefficients = [Vector3<f64>; 7];
+
+// Advance `bodies` by `duration` seconds (negative to go backwards) with
+// the 15th order Gauss-Radau scheme of IAS15. The step size adapts so the
+// error stays at the level of round-off, so there is nothing to tune
+pub fn advance(bodies: &mut [Body], duration: f64, state: &mut IntegratorState,
+               forces: &Forces) -> f64 {
+    if bodies.is_empty() { return duration; }
+    let basis = newton_basis();
+
+    // The polynomial from each step is carried over to predict the next one
+    let mut b = vec![[Vector3::new(0.0, 0.0, 0.0); 7]; bodies.len()];
+    // Round-off lost when adding small increments to large positions and
+    // velocities, fed back in on the next step
+    let mut lost = vec![(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)); bodies.len()];
+    // Length of the last attempt and whether it was accepted
+    let mut last: Option<(f64, bool)> = None;
+
+    forces.update_accels(bodies);
+    adaptive_advance(duration, state, |dt, _| {
+        let h = dt.abs();
+        match last {
+            Some((last_h, true)) => predict(&mut b, h / last_h),
+            Some((last_h, false)) => rescale(&mut b, h / last_h),
+            None => ()
+        }
+        converge(bodies, dt, &mut b, &basis, forces);
+
+        let next = next_step(bodies, &b, h);
+        let accepted = next >= SAFETY * h;
+        last = Some((h, accepted));
+        if accepted {
+            for (body, (b, (lost_pos, lost_v))) in bodies.iter_mut().zip(b.iter().zip(lost.iter_mut())) {
+                let (dpos, dv) = increments(body, b, dt, 1.0);
+                compensated_add(&mut body.pos.coords, dpos, lost_pos);
+                compensated_add(&mut body.v, dv, lost_v);
+            }
+            forces.update_accels(bodies);
+        }
+        Attempt { accepted, factor: next / h }
+    })
+}
+
+// Coefficients of t^k in (t - h1)(t - h2)...(t - hj) for row j, which turn
+// the Newton form of the acceleration polynomial into powers of t
+fn newton_basis() -> [[f64; 7]; 7] {
+    let mut basis = [[0.0; 7]; 7];
+    basis[0][0] = 1.0;
+    for (j, &h) in NODES.iter().enumerate().take(7).skip(1) {
+        let prev = basis[j - 1];
+        for (k, c) in basis[j].iter_mut().enumerate().take(j + 1) {
+            let shifted = if k > 0 { prev[k - 1] } else { 0.0 };
+            *c = shifted - h * prev[k];
+        }
+    }
+    basis
+}
+
+// Newton form coefficients (divided differences) of the polynomial `b`
+fn to_newton(b: &Coefficients, basis: &[[f64; 7]; 7]) -> Coefficients {
+    let mut g = *b;
+    for j in (0..7).rev() {
+        let higher = (j + 1..7).fold(Vector3::new(0.0, 0.0, 0.0), |sum, i| sum + basis[i][j] * g[i]);
+        g[j] -= higher;
+    }
+    g
+}
+
+// Changes in position and velocity a fraction `s` into a step of `dt`
+// seconds, integrating the acceleration polynomial `b` exactly
+fn increments(body: &Body, b: &Coefficients, dt: f64,
+              s: f64) -> (Vector3<f64>, Vector3<f64>) {
+    let mut dpos = s * s / 2.0 * body.a;
+    let mut dv = s * body.a;
+    let mut power = s * s; // s^(k+2)
+    for (k, b) in b.iter().enumerate() {
+        let k = k as f64;
+        dv += power / (k + 2.0) * b;
+        dpos += power * s / ((k + 2.0) * (k + 3.0)) * b;
+        power *= s;
+    }
+    (dt * s * body.v + dt * dt * dpos, dt * dv)
+}
+
+// Iterate the substeps until the polynomial `b` for a step of `dt` seconds
+// stops changing, starting from its current (predicted) value
+fn converge(bodies: &[Body], dt: f64, b: &mut [Coefficients], basis: &[[f64; 7]; 7],
+            forces: &Forces) {
+    let max_a = bodies.iter().map(|b| b.a.norm()).fold(0.0, f64::max);
+    if max_a == 0.0 { return; }
+
+    let mut g: Vec<Coefficients> = b.iter().map(|b| to_newton(b, basis)).collect();
+    let mut tmp = bodies.to_vec();
+    let mut last_change = f64::INFINITY;
+    for iteration in 0..MAX_ITERATIONS {
+        let mut max_change: f64 = 0.0;
+        for (n, &s) in NODES.iter().enumerate().skip(1) {
+            for ((t, body), b) in tmp.iter_mut().zip(bodies).zip(b.iter()) {
+                let (dpos, dv) = increments(body, b, dt, s);
+                t.pos = body.pos + dpos;
+                t.v = body.v + dv;
+            }
+            forces.update_accels(&mut tmp);
+
+            for ((t, body), (b, g)) in tmp.iter().zip(bodies).zip(b.iter_mut().zip(g.iter_mut())) {
+                // Divided difference through the substeps so far
+                let mut d = (t.a - body.a) / s;
+                for (g, h) in g.iter().zip(&NODES[1..n]) {
+                    d = (d - g) / (s - h);
+                }
+
+                let change = d - g[n - 1];
+                g[n - 1] = d;
+                for (b, c) in b.iter_mut().zip(&basis[n - 1][..n]) {
+                    *b += *c * change;
+                }
+                if n == 7 { max_change = max_change.max(change.norm()); }
+            }
+        }
+
+        // Stop once converged to round-off, or if it starts to oscillate
+        let change = max_change / max_a;
+        if change < 1e-16 || (iteration > 1 && change >= last_change) { break; }
+        last_change = change;
+    }
+}
+
+// Length of the next step after one of `h` seconds with polynomials `b`,
+// from the time scale on which each body's acceleration changes at the end
+// of the step (Pham, Rein & Spiegel 2024). Unlike the size of the last term
+// of the polynomial, this isn't swamped by round-off when forces nearly
+// cancel, e.g. for a body falling through an atmosphere at terminal speed
+fn next_step(bodies: &[Body], b: &[Coefficients], h: f64) -> f64 {
+    let mut min_timescale_squared = f64::INFINITY;
+    for (body, b) in bodies.iter().zip(b) {
+        // Acceleration and its first two derivatives at the end of the step,
+        // in units of the step
+        let mut a = body.a;
+        let (mut da, mut d2a) = (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0));
+        for (k, b) in b.iter().enumerate() {
+            let k = k as f64;
+            a += b;
+            da += (k + 1.0) * b;
+            d2a += (k + 1.0) * k * b;
+        }
+
+        let (y2, y3, y4) = (a.norm_squared(), da.norm_squared(), d2a.norm_squared());
+        let timescale_squared = 2.0 * y2 / (y3 + (y2 * y4).sqrt());
+        if y2 > 0.0 && timescale_squared.is_normal() {
+            min_timescale_squared = min_timescale_squared.min(timescale_squared);
+        }
+    }
+
+    if min_timescale_squared.is_finite() {
+        min_timescale_squared.sqrt() * h * (5040.0 * EPSILON).powf(1.0 / 7.0)
+    } else {
+        h / SAFETY
+    }
+}
+
+// Reuse the polynomial from a rejected step for a retry `ratio` times as long
+fn rescale(b: &mut [Coefficients], ratio: f64) {
+    for b in b.iter_mut() {
+        let mut q = ratio;
+        for b in b.iter_mut() {
+            *b *= q;
+            q *= ratio;
+        }
+    }
+}
+
+// Extrapolate the polynomial from an accepted step to the next step,
+// `ratio` times as long
+fn predict(b: &mut [Coefficients], ratio: f64) {
+    // Too far to extrapolate to be of any use
+    if ratio > 20.0 {
+        for b in b.iter_mut() { *b = [Vector3::new(0.0, 0.0, 0.0); 7]; }
+        return;
+    }
+
+    for b in b.iter_mut() {
+        let old = *b;
+        let mut q = ratio;
+        for (k, b_k) in b.iter_mut().enumerate() {
+            // Re-expand the sum of b_j t^(j+1) around t = 1, which takes
+            // binomial coefficients C(j+1, k+1)
+            let mut sum = Vector3::new(0.0, 0.0, 0.0);
+            let mut binomial = 1.0;
+            for (j, b_j) in old.iter().enumerate().skip(k) {
+                sum += binomial * b_j;
+                binomial = binomial * (j + 2) as f64 / (j + 1 - k) as f64;
+            }
+            *b_k = q * sum;
+            q *= ratio;
+        }
+    }
+}
+
+// Kahan summation, keeping track of the low bits lost in `lost`
+fn compensated_add(x: &mut Vector3<f64>, dx: Vector3<f64>, lost: &mut Vector3<f64>) {
+    let y = dx - *lost;
+    let sum = *x + y;
+    *lost = (sum - *x) - y;
+    *x = sum;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::tests::{energy, sun_earth};
+
+    #[test]
+    fn two_body_energy_drift() {
+        let mut bodies = sun_earth();
+        let forces = Forces::default();
+        let mut state = IntegratorState::default();
+        let e0 = energy(&bodies);
+
+        // Ten years, in steps of a frame each
+        for _ in 0..300 {
+            assert_eq!(advance(&mut bodies, 1e6, &mut state, &forces), 1e6);
+        }
+        assert!(((energy(&bodies) - e0) / e0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn runs_backwards() {
+        let start = sun_earth();
+        let mut bodies = start.clone();
+        let forces = Forces::default();
+        let mut state = IntegratorState::default();
+        advance(&mut bodies, 1e7, &mut state, &forces);
+        advance(&mut bodies, -1e7, &mut state, &forces);
+        assert!((bodies[1].pos - start[1].pos).norm() < 1.0);
+    }
+}
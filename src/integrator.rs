@@ -0,0 +1,221 @@
+use crate::body::Body;
+use crate::forces::Forces;
+use crate::rk45;
+use crate::hermite;
+use crate::ias15;
+use crate::bulirsch_stoer;
+use crate::wisdom_holman;
+
+// Bounds on how much the step size of an adaptive integrator may change
+// after a single attempt
+pub const MIN_FACTOR: f64 = 0.2;
+const MAX_FACTOR: f64 = 4.0;
+
+// Give up on the rest of the frame rather than stall the app
+const MAX_ATTEMPTS: u32 = 10000;
+
+// Schemes used to advance bodies by one step. Euler is kept around
+// mostly for comparison, as it does not conserve energy and orbits
+// slowly spiral outwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Integrator {
+    Euler,
+    Leapfrog,
+    VelocityVerlet,
+    Rk45,
+    Hermite,
+    WisdomHolman,
+    Ias15,
+    BulirschStoer
+}
+
+// Settings and bookkeeping for integrators beyond the step size
+#[derive(Clone)]
+pub struct IntegratorState {
+    pub rtol: f64, // Relative tolerance
+    pub atol: f64, // Absolute tolerance (in metres or metres per second)
+    pub eta: f64, // Accuracy parameter for Aarseth's timestep criterion
+    pub h: f64, // Size of the next substep to attempt
+    pub accepted: u32, // Substeps accepted during the last frame
+    pub rejected: u32, // Substeps rejected during the last frame
+    pub central_body: Option<usize> // For Wisdom-Holman, or None to pick the most massive
+}
+
+impl Default for IntegratorState {
+    fn default() -> IntegratorState {
+        IntegratorState {
+            rtol: 1e-6,
+            atol: 1e-3,
+            eta: 0.02,
+            h: 0.0,
+            accepted: 0,
+            rejected: 0,
+            central_body: None
+        }
+    }
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 8] = [
+        Integrator::Euler,
+        Integrator::Leapfrog,
+        Integrator::VelocityVerlet,
+        Integrator::Rk45,
+        Integrator::Hermite,
+        Integrator::WisdomHolman,
+        Integrator::Ias15,
+        Integrator::BulirschStoer
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Integrator::Euler => "Euler",
+            Integrator::Leapfrog => "Leapfrog (KDK)",
+            Integrator::VelocityVerlet => "Velocity Verlet",
+            Integrator::Rk45 => "RK45 (Dormand-Prince)",
+            Integrator::Hermite => "Hermite (4th order)",
+            Integrator::WisdomHolman => "Wisdom-Holman",
+            Integrator::Ias15 => "IAS15 (Gauss-Radau)",
+            Integrator::BulirschStoer => "Bulirsch-Stoer"
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        matches!(self, Integrator::Rk45 | Integrator::Hermite | Integrator::Ias15 |
+                       Integrator::BulirschStoer)
+    }
+
+    // Advance bodies by `dt` seconds. Fixed step integrators take a single
+    // step, while adaptive ones take as many substeps as `state` requires.
+    // Returns the time actually covered, which is short of `dt` if an
+    // adaptive integrator gave up partway
+    pub fn advance(&self, bodies: &mut [Body], dt: f64, state: &mut IntegratorState,
+                   forces: &Forces) -> f64 {
+        match self {
+            Integrator::Euler => { euler(bodies, dt, forces); dt }
+            Integrator::Leapfrog => { leapfrog(bodies, dt, forces); dt }
+            Integrator::VelocityVerlet => { velocity_verlet(bodies, dt, forces); dt }
+            Integrator::Rk45 => rk45::advance(bodies, dt, state, forces),
+            Integrator::Hermite => hermite::advance(bodies, dt, state, forces),
+            Integrator::WisdomHolman => {
+                wisdom_holman::step(bodies, dt, state.central_body, forces);
+                dt
+            }
+            Integrator::Ias15 => ias15::advance(bodies, dt, state, forces),
+            Integrator::BulirschStoer => bulirsch_stoer::advance(bodies, dt, state, forces)
+        }
+    }
+}
+
+// Outcome of one substep attempted by an adaptive integrator
+pub struct Attempt {
+    pub accepted: bool, // Whether the bodies were moved
+    pub factor: f64 // Suggested size of the next substep relative to this one
+}
+
+// Cover `duration` seconds (negative to go backwards) in substeps whose
+// size adapts, starting from `state.h`. `try_step` attempts a substep of
+// the given signed length and moves the bodies only if it accepts it.
+// Returns the time covered, which is short of `duration` if the attempts
+// ran out
+pub fn adaptive_advance<F>(duration: f64, state: &mut IntegratorState, mut try_step: F) -> f64
+    where F: FnMut(f64, &IntegratorState) -> Attempt {
+    state.accepted = 0;
+    state.rejected = 0;
+    if duration == 0.0 { return duration; }
+
+    let direction = duration.signum();
+    let mut remaining = duration.abs();
+    if state.h <= 0.0 { state.h = remaining; }
+
+    let mut attempts = 0;
+    while remaining > 0.0 && attempts < MAX_ATTEMPTS {
+        attempts += 1;
+        let truncated = state.h > remaining;
+        let h = if truncated { remaining } else { state.h };
+        let attempt = try_step(direction * h, state);
+
+        let factor = attempt.factor.clamp(MIN_FACTOR, MAX_FACTOR);
+        if attempt.accepted {
+            remaining -= h;
+            state.accepted += 1;
+            // Don't let a short final step (to land exactly on the frame
+            // boundary) shrink the step size used for the next frame
+            if !truncated { state.h = h * factor; }
+        }
+        else {
+            state.rejected += 1;
+            state.h = h * factor.min(1.0);
+        }
+    }
+    direction * (duration.abs() - remaining)
+}
+
+fn euler(bodies: &mut [Body], dt: f64,
+         forces: &Forces) {
+    forces.update_accels(bodies);
+    for b in bodies.iter_mut() {
+        b.pos += dt * b.v;
+        b.v += dt * b.a;
+    }
+}
+
+// Kick-drift-kick: half kick, full drift, then another half kick with the
+// accelerations at the new positions
+pub fn leapfrog(bodies: &mut [Body], dt: f64,
+            forces: &Forces) {
+    forces.update_accels(bodies);
+    for b in bodies.iter_mut() {
+        b.v += 0.5 * dt * b.a;
+        b.pos += dt * b.v;
+    }
+
+    forces.update_accels(bodies);
+    for b in bodies.iter_mut() {
+        b.v += 0.5 * dt * b.a;
+    }
+}
+
+// Positions are advanced with a second order Taylor expansion, and
+// velocities with the average of the old and new accelerations
+fn velocity_verlet(bodies: &mut [Body], dt: f64,
+                   forces: &Forces) {
+    forces.update_accels(bodies);
+    let old_accels: Vec<_> = bodies.iter().map(|b| b.a).collect();
+    for b in bodies.iter_mut() {
+        b.pos += dt * b.v + 0.5 * dt * dt * b.a;
+    }
+
+    forces.update_accels(bodies);
+    for (b, a) in bodies.iter_mut().zip(old_accels) {
+        b.v += 0.5 * dt * (a + b.a);
+    }
+}
+
+// Helpers shared by the tests of the integrators
+#[cfg(test)]
+pub mod tests {
+    use ggez::nalgebra::{Point3, Vector3};
+
+    use crate::body::{Body, G};
+
+    // Total kinetic and potential energy
+    pub fn energy(bodies: &[Body]) -> f64 {
+        let mut e = 0.0;
+        for (idx, b) in bodies.iter().enumerate() {
+            e += 0.5 * b.mass * b.v.norm_squared();
+            for b_ in &bodies[idx + 1..] {
+                e -= G * b.mass * b_.mass / (b.pos - b_.pos).norm();
+            }
+        }
+        e
+    }
+
+    // The Sun and the Earth on a slightly eccentric orbit
+    pub fn sun_earth() -> Vec<Body> {
+        vec![
+            Body::new(1.989e30, 0.0, Point3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)),
+            Body::new(5.97e24, 0.0, Point3::new(1.5e11, 0.0, 0.0), Vector3::new(0.0, 38000.0, 0.0))
+        ]
+    }
+}
@@ -1,13 +1,55 @@
+use std::time::Duration;
+
 use ggez;
 use ggez::conf;
 use ggez::input::{mouse, keyboard};
-use ggez::nalgebra::{Point2, Vector2};
+use ggez::nalgebra::{Point2, Point3, Vector3};
 use ggez::event::{self, KeyCode, KeyMods, MouseButton};
-use ggez::graphics::{self, Color, DrawMode, DrawParam};
-use ggez::{Context, ContextBuilder, GameResult};
+use ggez::graphics::{self, Color, DrawMode, DrawParam, MeshBuilder, Rect};
+use ggez::{timer, Context, ContextBuilder, GameResult};
 
 mod body;
-use crate::body::Body;
+use crate::body::{Body, G, L_SUN};
+
+mod spacecraft;
+
+mod forces;
+use crate::forces::Forces;
+
+mod force_model;
+
+mod periodic;
+use crate::periodic::PeriodicBox;
+
+mod potential;
+use crate::potential::{contour_levels, contour_segments};
+
+mod octree;
+
+mod camera;
+use crate::camera::Camera;
+
+mod frame;
+use crate::frame::Frame;
+
+mod collision;
+use crate::collision::{CollisionMode, merge_colliding, bounce};
+
+mod history;
+use crate::history::History;
+
+mod integrator;
+use crate::integrator::{Integrator, IntegratorState};
+
+mod rk45;
+
+mod hermite;
+
+mod wisdom_holman;
+
+mod ias15;
+
+mod bulirsch_stoer;
 
 mod ui;
 use crate::ui::UiWrapper;
@@ -15,108 +57,448 @@ use crate::ui::UiWrapper;
 mod state;
 use crate::state::*;
 
+// Pixels between samples of the background potentials, and how many
+// contours to draw
+const CONTOUR_SPACING: f32 = 10.0;
+const CONTOUR_LEVELS: usize = 12;
+
+// Cap on physics steps per frame, beyond which the simulation slows down
+// instead of taking ever longer frames to catch up
+const MAX_STEPS_PER_FRAME: usize = 1000;
+
+// Real time a frame may spend on physics before the rest of its steps are
+// dropped, for when even the capped steps are too slow
+const FRAME_BUDGET: Duration = Duration::from_millis(50);
+
+// Radians the camera turns per pixel of dragging with the right button
+const CAMERA_SENSITIVITY: f32 = 0.01;
+
 impl GameState {
     fn new(ctx: &mut Context) -> GameResult<GameState> {
         let screen_coords = graphics::screen_coordinates(ctx);
         let game_state = GameState {
             size: (screen_coords.w, screen_coords.h),
-            origin: Point2::new(0.0, 0.0),
-            scale: 1e+9_f32,
+            origin: Point3::new(0.0, 0.0, 0.0),
+            scale: 1e+9_f64,
+            camera: Camera::default(),
+            frame: Frame::Inertial,
             bodies: Vec::new(),
             dt: 10000.0,
+            speed: 1e+6_f64,
+            time: 0.0,
+            accumulator: 0.0,
+            lagging: false,
+            integrator: Integrator::Leapfrog,
+            integration: IntegratorState::default(),
+            forces: Forces::default(),
+            collisions: CollisionMode::Off,
+            restitution: 1.0,
+            history: History::default(),
+            show_contours: true,
             paused: false,
             reversed: false
         };
         Ok(game_state)
     }
 
-    fn local_to_global_coords(&self, pos: &Point2<f32>) -> Point2<f32> {
+    // Point of the xy-plane under the cursor (see `Camera::to_global`). Like
+    // `global_to_local_coords`, this works in the chosen frame
+    fn local_to_global_coords(&self, pos: &Point2<f32>) -> Point3<f64> {
         let (center_x, center_y) = (self.size.0 / 2.0, self.size.1 / 2.0);
-        let global_x = self.origin.x + (pos.x - center_x) * self.scale;
-        let global_y = self.origin.y + (pos.y - center_y) * self.scale;
-        Point2::new(global_x, global_y)
+        let x = (pos.x - center_x) as f64 * self.scale;
+        let y = (pos.y - center_y) as f64 * self.scale;
+        self.camera.to_global(&self.origin, x, y)
+    }
+
+    // Offsets from the origin are taken in f64 before narrowing, so bodies
+    // far from the global origin still render in the right place
+    fn global_to_local_coords(&self, pos: &Point3<f64>) -> Point2<f32> {
+        self.view_to_local_coords(&self.camera.to_view(&(pos - self.origin)))
     }
 
-    fn global_to_local_coords(&self, pos: &Point2<f32>) -> Point2<f32> {
+    // Screen position of an offset from the origin in view axes
+    fn view_to_local_coords(&self, view: &Vector3<f64>) -> Point2<f32> {
         let (center_x, center_y) = (self.size.0 / 2.0, self.size.1 / 2.0);
-        let local_x = center_x + (pos.x - self.origin.x) / self.scale;
-        let local_y = center_y + (pos.y - self.origin.y) / self.scale;
+        let local_x = center_x + (view.x / self.scale) as f32;
+        let local_y = center_y + (view.y / self.scale) as f32;
         Point2::new(local_x, local_y)
     }
 
-    fn add_body(&mut self, mass: f32, pos: Point2<f32>, v: Vector2<f32>) {
-        self.bodies.push(Body {
-            mass, pos, v,
-            a: Vector2::new(0.0, 0.0),
-            color: [1.0, 1.0, 1.0, 1.0]
-        });
+    fn add_body(&mut self, mass: f64, radius: f64, pos: Point3<f64>, v: Vector3<f64>) {
+        self.bodies.push(Body::new(mass, radius, pos, v));
     }
 
-    fn draw_body(&self, ctx: &mut Context,
-                 pos: &Point2<f32>, color: &[f32; 4]) -> GameResult<()> {
+    // Add `count` test particles with the given `beta` on circular orbits
+    // around body `idx`, spread evenly over the annulus between `inner` and
+    // `outer` metres, parallel to the xy-plane
+    fn add_ring(&mut self, idx: usize, count: usize, inner: f64, outer: f64, beta: f64) {
+        let (center, v) = (self.bodies[idx].pos, self.bodies[idx].v);
+        // Radiation pressure takes away some of the pull of a luminous body
+        let gm = G * self.bodies[idx].mass - self.bodies[idx].radiation_gm(beta);
+        let golden_angle = std::f64::consts::PI * (3.0 - 5.0_f64.sqrt());
+        for k in 0..count {
+            let f = (k as f64 + 0.5) / count as f64;
+            let r = (inner * inner + f * (outer * outer - inner * inner)).sqrt();
+            let angle = k as f64 * golden_angle;
+            let dir = Vector3::new(angle.cos(), angle.sin(), 0.0);
+            let speed = if r > 0.0 && gm > 0.0 { (gm / r).sqrt() } else { 0.0 };
+
+            self.add_body(0.0, 0.0, center + r * dir, v + speed * Vector3::new(-dir.y, dir.x, 0.0));
+            let b = self.bodies.last_mut().unwrap();
+            b.test_particle = true;
+            b.beta = beta;
+            b.color = [0.7, 0.7, 0.7, 1.0];
+        }
+    }
+
+    // Remove every test particle. Returns where each old index ended up,
+    // if the body is still around
+    fn remove_test_particles(&mut self) -> Vec<Option<usize>> {
+        let mut next = 0;
+        let new_idx = self.bodies.iter()
+            .map(|b| {
+                if b.test_particle { return None; }
+                next += 1;
+                Some(next - 1)
+            })
+            .collect();
+        self.bodies.retain(|b| !b.test_particle);
+        new_idx
+    }
+
+    // Bodies are drawn to scale, but never smaller than a 7 pixel marker
+    fn draw_body(&self, mesh: &mut MeshBuilder, dest: Point2<f32>,
+                 radius: f64, color: &[f32; 4]) {
         let (r, g, b, a) = (color[0], color[1], color[2], color[3]);
-        let circle = graphics::Mesh::new_circle(
-            ctx,
-            DrawMode::fill(),
-            Point2::new(0.0, 0.0),
-            7.0,
-            0.1,
-            Color::new(r, g, b, a)
-        )?;
-
-        let dest = self.global_to_local_coords(&pos);
-        graphics::draw(ctx, &circle, DrawParam::default().dest(dest))?;
+        let radius = ((radius / self.scale) as f32).max(7.0);
+        mesh.circle(DrawMode::fill(), dest, radius, 0.1, Color::new(r, g, b, a));
+    }
 
-        Ok(())
+    // Test particles are plain 2 pixel dots, skipped when off screen, so
+    // thousands of them stay cheap to draw. Returns whether it was drawn
+    fn draw_particle(&self, mesh: &mut MeshBuilder, dest: Point2<f32>, color: &[f32; 4]) -> bool {
+        if dest.x < 0.0 || dest.y < 0.0 || dest.x > self.size.0 || dest.y > self.size.1 {
+            return false;
+        }
+
+        let (r, g, b, a) = (color[0], color[1], color[2], color[3]);
+        mesh.rectangle(DrawMode::fill(), Rect::new(dest.x - 1.0, dest.y - 1.0, 2.0, 2.0),
+                       Color::new(r, g, b, a));
+        true
     }
 
+    // All bodies go into a single mesh, as issuing one draw call per body
+    // becomes the bottleneck with thousands of bodies. They're added from
+    // back to front, so nearer bodies are drawn over those behind them
     fn draw_bodies(&self, ctx: &mut Context) -> GameResult<()> {
-        for b in &self.bodies[..] {
-            self.draw_body(ctx, &b.pos, &b.color)?;
+        if self.bodies.is_empty() { return Ok(()); }
+
+        // Offsets of the bodies from the origin in view axes, worked out
+        // once for both sorting by depth and drawing
+        let frame = self.frame.transform(&self.bodies);
+        let rotation = self.camera.rotation();
+        let views: Vec<Vector3<f64>> = self.bodies.iter()
+            .map(|b| rotation * (frame.to_frame(&b.pos) - self.origin))
+            .collect();
+        let mut order: Vec<usize> = (0..self.bodies.len()).collect();
+        order.sort_by(|&idx, &idx_| {
+            views[idx_].z.partial_cmp(&views[idx].z).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let mut mesh = MeshBuilder::new();
+        let mut empty = true;
+        for idx in order {
+            let b = &self.bodies[idx];
+            let dest = self.view_to_local_coords(&views[idx]);
+            if b.test_particle {
+                if self.draw_particle(&mut mesh, dest, &b.color) { empty = false; }
+            }
+            else {
+                self.draw_body(&mut mesh, dest, b.radius, &b.color);
+                empty = false;
+            }
         }
+        // Nothing may be left if all bodies are off-screen particles
+        if empty { return Ok(()); }
+
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())?;
         Ok(())
     }
 
-    fn update_bodies(&mut self) {
-        // Update accelerations for each body
-        if self.bodies.len() >= 2 {
-            for i in 0..self.bodies.len() {
-                let (left, right) = self.bodies.split_at_mut(i);
-                let b = &mut right[0];
-                for b_ in left {
-                    b.a += b.accel_towards(b_);
-                    b_.a += b_.accel_towards(b);
-                }
+    // Contours of the background potentials over the view, sampled on a
+    // coarse grid over the xy-plane
+    fn draw_contours(&self, ctx: &mut Context) -> GameResult<()> {
+        let potentials = &self.forces.potentials;
+        if !self.show_contours || potentials.is_empty() { return Ok(()); }
+
+        let nx = (self.size.0 / CONTOUR_SPACING) as usize + 2;
+        let ny = (self.size.1 / CONTOUR_SPACING) as usize + 2;
+        let frame = self.frame.transform(&self.bodies);
+        let mut values = Vec::with_capacity(nx * ny);
+        for j in 0..ny {
+            for i in 0..nx {
+                let local = Point2::new(i as f32 * CONTOUR_SPACING, j as f32 * CONTOUR_SPACING);
+                let pos = frame.to_global(&self.local_to_global_coords(&local));
+                values.push(potentials.iter().map(|p| p.value(&pos)).sum());
             }
         }
 
-        // Update each body
-        for b in &mut self.bodies[..] {
-            if self.reversed {
-                b.pos -= self.dt * b.v;
-                b.v -= self.dt * b.a;
+        let mut mesh = MeshBuilder::new();
+        let mut empty = true;
+        for level in contour_levels(&values, CONTOUR_LEVELS) {
+            for [(x0, y0), (x1, y1)] in contour_segments(&values, nx, ny, level) {
+                let points = [
+                    Point2::new(x0 as f32 * CONTOUR_SPACING, y0 as f32 * CONTOUR_SPACING),
+                    Point2::new(x1 as f32 * CONTOUR_SPACING, y1 as f32 * CONTOUR_SPACING)
+                ];
+                mesh.line(&points, 1.0, Color::new(0.2, 0.4, 0.7, 1.0))?;
+                empty = false;
             }
-            else {
-                b.pos += self.dt * b.v;
-                b.v += self.dt * b.a;
+        }
+        if empty { return Ok(()); }
+
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())?;
+        Ok(())
+    }
+
+    // Outline of the periodic domain, if there is one. Edges seen end-on
+    // are skipped
+    fn draw_domain(&self, ctx: &mut Context) -> GameResult<()> {
+        let periodic = match &self.forces.periodic {
+            Some(periodic) => periodic,
+            None => return Ok(())
+        };
+
+        let frame = self.frame.transform(&self.bodies);
+        let half = periodic.size / 2.0;
+        let corner = |k: usize| {
+            let side = |bit: usize| if k & bit == 0 { -half } else { half };
+            let pos = Point3::new(side(1), side(2), side(4));
+            self.global_to_local_coords(&frame.to_frame(&pos))
+        };
+
+        let mut mesh = MeshBuilder::new();
+        let mut empty = true;
+        for k in 0..8 {
+            for &bit in [1, 2, 4].iter() {
+                if k & bit != 0 { continue; }
+
+                let points = [corner(k), corner(k | bit)];
+                if (points[1] - points[0]).norm() < 1.0 { continue; }
+                mesh.line(&points, 1.0, Color::new(0.5, 0.5, 0.5, 1.0))?;
+                empty = false;
+            }
+        }
+        if empty { return Ok(()); }
+
+        let mesh = mesh.build(ctx)?;
+        graphics::draw(ctx, &mesh, DrawParam::default())?;
+        Ok(())
+    }
+
+    // Sizes of the steps to take to catch up with `elapsed` real seconds.
+    // Fixed step integrators take as many steps of `dt` as fit, carrying
+    // the remainder over to the next frame, while adaptive ones cover the
+    // whole time in one go (and `update_bodies` reports lag if they give up)
+    fn steps_for_frame(&mut self, elapsed: f64) -> Vec<f64> {
+        self.accumulator += elapsed * self.speed;
+        if self.integrator.is_adaptive() {
+            // Cover no more than the same number of substeps at the current
+            // step size, dropping the rest of the backlog like below
+            let max_duration = MAX_STEPS_PER_FRAME as f64 * self.integration.h;
+            self.lagging = self.integration.h > 0.0 && self.accumulator > max_duration;
+            let duration = if self.lagging { max_duration } else { self.accumulator };
+            self.accumulator = 0.0;
+            return vec![duration];
+        }
+
+        let steps = (self.accumulator / self.dt).floor() as usize;
+        // Drop the backlog rather than fall further behind every frame
+        self.lagging = steps > MAX_STEPS_PER_FRAME;
+        if self.lagging {
+            self.accumulator = 0.0;
+            return vec![self.dt; MAX_STEPS_PER_FRAME];
+        }
+
+        self.accumulator -= steps as f64 * self.dt;
+        vec![self.dt; steps]
+    }
+
+    // If bodies were merged, returns where each old index ended up
+    fn update_bodies(&mut self, dt: f64) -> Option<Vec<usize>> {
+        // Retrace recorded steps, and only integrate backwards once we run
+        // out of them
+        if self.reversed {
+            // Steps since the last snapshot are redone with the rest of the
+            // state, so the history is taken out meanwhile
+            let mut history = std::mem::take(&mut self.history);
+            let current = self.step_settings();
+            let popped = history.pop(|bodies, from, to, settings| {
+                self.apply_settings(settings);
+                std::mem::swap(&mut self.bodies, bodies);
+                self.time = from;
+                self.step_bodies(to - from);
+                std::mem::swap(&mut self.bodies, bodies);
+            });
+            self.apply_settings(&current);
+            self.history = history;
+
+            if let Some((time, bodies)) = popped {
+                self.time = time;
+                self.bodies = bodies;
+                // The restored state may have fewer bodies than the frame
+                // follows or the central body refers to
+                let len = self.bodies.len();
+                self.frame = self.frame.reindex(|idx| if idx < len { Some(idx) } else { None });
+                let central = &mut self.integration.central_body;
+                *central = central.filter(|&idx| idx < len);
+                return None;
             }
-            b.a = Vector2::new(0.0, 0.0);
         }
+        else {
+            let settings = self.step_settings();
+            self.history.push(self.time, &self.bodies, settings);
+        }
+
+        let new_idx = self.step_bodies(if self.reversed { -dt } else { dt });
+        if let Some(new_idx) = &new_idx {
+            let central = &mut self.integration.central_body;
+            *central = central.map(|idx| new_idx[idx]);
+            self.frame = self.frame.reindex(|idx| Some(new_idx[idx]));
+        }
+        if !self.reversed { self.history.landed(&self.bodies); }
+        new_idx
+    }
+
+    fn step_settings(&self) -> StepSettings {
+        StepSettings {
+            integrator: self.integrator,
+            integration: self.integration.clone(),
+            collisions: self.collisions,
+            restitution: self.restitution,
+            solver: self.forces.solver,
+            theta: self.forces.theta,
+            softening: self.forces.softening,
+            enabled: self.forces.models.iter().map(|(_, enabled)| *enabled).collect(),
+            potentials: self.forces.potentials.clone(),
+            box_size: self.forces.periodic.as_ref().map(|periodic| periodic.size)
+        }
+    }
+
+    fn apply_settings(&mut self, settings: &StepSettings) {
+        self.integrator = settings.integrator;
+        self.integration = settings.integration.clone();
+        self.collisions = settings.collisions;
+        self.restitution = settings.restitution;
+        self.forces.solver = settings.solver;
+        self.forces.theta = settings.theta;
+        self.forces.softening = settings.softening;
+        for ((_, enabled), &was) in self.forces.models.iter_mut().zip(&settings.enabled) {
+            *enabled = was;
+        }
+        self.forces.potentials.clone_from(&settings.potentials);
+        // Building the Ewald table takes a while, so keep it if it fits
+        if self.forces.periodic.as_ref().map(|periodic| periodic.size) != settings.box_size {
+            self.forces.periodic = settings.box_size.map(PeriodicBox::new);
+        }
+    }
+
+    // Advance bodies by `dt` seconds (negative to go backwards), then
+    // resolve collisions. If bodies were merged, returns where each old
+    // index ended up
+    fn step_bodies(&mut self, dt: f64) -> Option<Vec<usize>> {
+        let start: Vec<_> = self.bodies.iter().map(|b| b.pos).collect();
+
+        // Split the step wherever an engine turns on or off, so each burn
+        // lasts exactly as long as planned
+        let end = self.time + dt;
+        let mut times: Vec<f64> = self.bodies.iter()
+            .filter_map(|b| b.spacecraft.as_ref())
+            .flat_map(|s| s.switches(self.time, end))
+            .collect();
+        times.sort_by(|t, t_| t.partial_cmp(t_).unwrap_or(std::cmp::Ordering::Equal));
+        if dt < 0.0 { times.reverse(); }
+        times.push(end);
+
+        for t in times {
+            let mid = (self.time + t) / 2.0;
+            for s in self.bodies.iter_mut().filter_map(|b| b.spacecraft.as_mut()) {
+                s.burn = s.burn_at(mid);
+            }
+            let duration = t - self.time;
+            let covered = self.integrator.advance(&mut self.bodies, duration,
+                                                  &mut self.integration, &self.forces);
+            // The integrator gave up partway, so the clock only moves as far
+            // as the bodies did and the rest of the step is dropped
+            if covered != duration {
+                self.time += covered;
+                self.lagging = true;
+                break;
+            }
+            self.time = t;
+        }
+
+        // Pinned bodies never accelerate, but make sure nothing nudged them
+        for (b, pos) in self.bodies.iter_mut().zip(start.iter()) {
+            if b.pinned {
+                b.pos = *pos;
+                b.v = Vector3::new(0.0, 0.0, 0.0);
+            }
+        }
+
+        let new_idx = match self.collisions {
+            CollisionMode::Off => None,
+            CollisionMode::Merge => merge_colliding(&mut self.bodies, &start),
+            CollisionMode::Bounce => {
+                bounce(&mut self.bodies, &start, dt, self.restitution);
+                None
+            }
+        };
+
+        // Bring back whatever left the periodic domain through the opposite
+        // face. Collisions are only found within the domain, not across faces
+        if let Some(periodic) = &self.forces.periodic {
+            for b in self.bodies.iter_mut() {
+                b.pos = periodic.wrap(&b.pos);
+            }
+        }
+        new_idx
     }
 }
 
 impl UiState {
-    fn new() -> UiState {
+    fn new(game_state: &GameState) -> UiState {
         UiState {
             mouse_pos: Point2::new(0.0, 0.0),
             opened: true,
             scale_change: 1.0,
             input_scale: 1e+9_f32,
             input_dt: 10000.0,
+            input_speed: 1e+6_f32,
+            input_rtol_exp: -6,
+            input_atol_exp: -3,
+            input_eta: 0.02,
+            input_theta: 0.5,
+            solver_error: None,
+            input_softening: 0.0,
+            input_threads: game_state.forces.threads as i32,
+            input_restitution: 1.0,
+            input_box_size: 600.0,
+            input_frame_pair: [0, 1],
+            input_ring_count: 1000,
+            input_ring_radii: [50.0, 100.0],
+            input_ring_beta: 0.0,
             selected_body_idx: None,
             input_mass: 0.0,
-            input_v: [0.0, 0.0],
-            input_pos: [0.0, 0.0],
+            input_v: [0.0, 0.0, 0.0],
+            input_radius: 0.0,
+            input_body_softening: 0.0,
+            input_luminosity: 0.0,
+            input_beta: 0.0,
+            input_atmosphere: [0.0, 0.0],
+            input_pos: [0.0, 0.0, 0.0],
             input_color: [1.0, 1.0, 1.0, 1.0]
         }
     }
@@ -124,9 +506,10 @@ impl UiState {
 
 impl GameInstance {
     fn new(ctx: &mut Context, hidpi_factor: f32) -> GameResult<GameInstance> {
+        let game_state = GameState::new(ctx)?;
         let instance = GameInstance {
-            game_state: GameState::new(ctx)?,
-            ui_state: UiState::new(),
+            ui_state: UiState::new(&game_state),
+            game_state,
             ui_wrapper: UiWrapper::new(ctx, hidpi_factor)
         };
 
@@ -135,26 +518,55 @@ impl GameInstance {
 }
 
 impl event::EventHandler for GameInstance {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult<()> {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         if !self.game_state.paused {
-            self.game_state.update_bodies();
+            let elapsed = timer::duration_to_f64(timer::delta(ctx));
+            let deadline = timer::time_since_start(ctx) + FRAME_BUDGET;
+            for dt in self.game_state.steps_for_frame(elapsed) {
+                if timer::time_since_start(ctx) > deadline {
+                    self.game_state.lagging = true;
+                    break;
+                }
+                if let Some(new_idx) = self.game_state.update_bodies(dt) {
+                    // Follow the selected body into whatever it merged with,
+                    // or drop it if a rewind earlier this frame left it stale
+                    self.ui_state.selected_body_idx = self.ui_state.selected_body_idx
+                        .and_then(|idx| new_idx.get(idx).copied());
+                }
+            }
+        }
+
+        // Rewinding may bring back a state with fewer bodies
+        if let Some(idx) = self.ui_state.selected_body_idx {
+            if idx >= self.game_state.bodies.len() {
+                self.ui_state.selected_body_idx = None;
+            }
         }
 
         if self.game_state.dt <= 1.0 {
             self.game_state.dt = 1.0;
             self.ui_state.input_dt = 1.0;
         }
-        else if self.game_state.dt >= 1e+10_f32 {
-            self.game_state.dt = 1e+10_f32;
+        else if self.game_state.dt >= 1e+10_f64 {
+            self.game_state.dt = 1e+10_f64;
             self.ui_state.input_dt = 1e+10_f32;
         }
 
+        if self.game_state.speed <= 1.0 {
+            self.game_state.speed = 1.0;
+            self.ui_state.input_speed = 1.0;
+        }
+        else if self.game_state.speed >= 1e+12_f64 {
+            self.game_state.speed = 1e+12_f64;
+            self.ui_state.input_speed = 1e+12_f32;
+        }
+
         if self.game_state.scale <= 1.0 {
             self.game_state.scale = 1.0;
             self.ui_state.input_scale = 1.0;
         }
-        else if self.game_state.scale >= 1e+15_f32 {
-            self.game_state.scale = 1e+15_f32;
+        else if self.game_state.scale >= 1e+15_f64 {
+            self.game_state.scale = 1e+15_f64;
             self.ui_state.input_scale = 1e+15_f32;
         }
 
@@ -163,6 +575,8 @@ impl event::EventHandler for GameInstance {
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
         graphics::clear(ctx, Color::new(0.0, 0.0, 0.0, 1.0));
+        self.game_state.draw_contours(ctx)?;
+        self.game_state.draw_domain(ctx)?;
         self.game_state.draw_bodies(ctx)?;
         self.ui_wrapper.update_ui(ctx, &mut self.game_state, &mut self.ui_state);
         graphics::present(ctx)?;
@@ -176,8 +590,14 @@ impl event::EventHandler for GameInstance {
             KeyCode::Q => { event::quit(ctx); return; }
             KeyCode::P => self.game_state.paused = !self.game_state.paused,
             KeyCode::R => self.game_state.reversed = !self.game_state.reversed,
-            KeyCode::Left => self.game_state.dt /= 2.0,
-            KeyCode::Right => self.game_state.dt *= 2.0,
+            KeyCode::Left => {
+                self.game_state.speed /= 2.0;
+                self.ui_state.input_speed /= 2.0;
+            }
+            KeyCode::Right => {
+                self.game_state.speed *= 2.0;
+                self.ui_state.input_speed *= 2.0;
+            }
             KeyCode::Up => {
                 self.game_state.scale /= 2.0;
                 self.ui_state.input_scale /= 2.0;
@@ -202,24 +622,44 @@ impl event::EventHandler for GameInstance {
         self.ui_wrapper.update_mouse_down(button);
         self.ui_state.mouse_pos = Point2::new(x, y);
 
+        // Test particles are only picked if there's no body under the cursor,
+        // so they don't get in the way of the bodies they crowd around.
+        // Otherwise the nearest to the camera wins
+        let frame = self.game_state.frame.transform(&self.game_state.bodies);
+        let mut picked: Option<(usize, bool, f64)> = None;
         for (idx, b) in self.game_state.bodies.iter().enumerate() {
-            let local_coords = self.game_state.global_to_local_coords(&b.pos);
+            let pos = frame.to_frame(&b.pos);
+            let local_coords = self.game_state.global_to_local_coords(&pos);
             let (dx, dy) = (local_coords.x - x, local_coords.y - y);
             let r_squared = dx.powi(2) + dy.powi(2);
+            let depth = self.game_state.camera.to_view(&(pos - self.game_state.origin)).z;
 
-            if r_squared < 25.0 {
-                self.ui_state.selected_body_idx = Some(idx);
+            let in_front = match picked {
+                None => true,
+                Some((_, test_particle, depth_)) => {
+                    if test_particle == b.test_particle { depth <= depth_ }
+                    else { test_particle }
+                }
+            };
+            if r_squared < 25.0 && in_front {
+                picked = Some((idx, b.test_particle, depth));
             }
         }
+        if let Some((idx, _, _)) = picked {
+            self.ui_state.selected_body_idx = Some(idx);
+        }
 
         let keys = keyboard::pressed_keys(ctx);
         if keys.contains(&KeyCode::LShift) || keys.contains(&KeyCode::RShift) {
-            let global_coords = self.game_state.local_to_global_coords(&Point2::new(x, y));
+            let global_coords = frame.to_global(
+                &self.game_state.local_to_global_coords(&Point2::new(x, y)));
             self.game_state.add_body(
-                1.989e+30_f32, // Sun's mass
+                1.989e+30_f64, // Sun's mass
+                6.957e+8_f64, // Sun's radius
                 global_coords,
-                Vector2::new(0.0, 0.0),
+                Vector3::new(0.0, 0.0, 0.0),
             );
+            self.game_state.bodies.last_mut().unwrap().luminosity = L_SUN;
             self.ui_state.selected_body_idx = Some(self.game_state.bodies.len()-1);
         }
     }
@@ -234,10 +674,18 @@ impl event::EventHandler for GameInstance {
         self.ui_wrapper.update_mouse_pos(x, y);
         if mouse::button_pressed(ctx, mouse::MouseButton::Left) {
             if let None = self.ui_state.selected_body_idx {
-                self.game_state.origin += Vector2::new(-dx * self.game_state.scale,
-                                                       -dy * self.game_state.scale);
+                // Pan along the screen, whichever way the camera faces
+                let pan = Vector3::new(-dx as f64 * self.game_state.scale,
+                                       -dy as f64 * self.game_state.scale, 0.0);
+                self.game_state.origin += self.game_state.camera.rotation().inverse() * pan;
             }
         }
+        if mouse::button_pressed(ctx, mouse::MouseButton::Right) {
+            let camera = &mut self.game_state.camera;
+            camera.yaw += (dx * CAMERA_SENSITIVITY) as f64;
+            camera.pitch = (camera.pitch + (dy * CAMERA_SENSITIVITY) as f64)
+                .clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+        }
     }
 
     fn mouse_wheel_event(&mut self, _ctx: &mut Context, x: f32, y: f32) {
@@ -0,0 +1,176 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+use crate::body::{Body, point_mass_accel};
+
+// Beyond this depth bodies are kept together in one leaf, so bodies at
+// (nearly) the same position don't make the tree infinitely deep
+const MAX_DEPTH: u32 = 48;
+
+struct Node {
+    center: Point3<f64>,
+    half_size: f64,
+    mass: f64,
+    com: Point3<f64>, // Centre of mass
+    children: Option<usize>, // Index of the first of 8 consecutive children
+    bodies: Vec<usize> // Indices of bodies held by a leaf
+}
+
+impl Node {
+    fn new(center: Point3<f64>, half_size: f64) -> Node {
+        Node {
+            center, half_size,
+            mass: 0.0,
+            com: Point3::new(0.0, 0.0, 0.0),
+            children: None,
+            bodies: Vec::new()
+        }
+    }
+
+    fn contains(&self, pos: &Point3<f64>) -> bool {
+        (pos.x - self.center.x).abs() <= self.half_size &&
+            (pos.y - self.center.y).abs() <= self.half_size &&
+            (pos.z - self.center.z).abs() <= self.half_size
+    }
+
+    fn octant(&self, pos: &Point3<f64>) -> usize {
+        let east = pos.x >= self.center.x;
+        let south = pos.y >= self.center.y;
+        let up = pos.z >= self.center.z;
+        (east as usize) | ((south as usize) << 1) | ((up as usize) << 2)
+    }
+}
+
+// Barnes-Hut tree over the bodies at their current positions. Distant
+// groups of bodies are approximated by a point mass at their centre of
+// mass, bringing the force pass down to O(n log n). Test particles are
+// left out, as they don't pull on anything
+pub struct Octree {
+    nodes: Vec<Node>
+}
+
+impl Octree {
+    pub fn new(bodies: &[Body]) -> Octree {
+        let mut min = Point3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for b in bodies.iter().filter(|b| !b.test_particle) {
+            min = Point3::new(min.x.min(b.pos.x), min.y.min(b.pos.y), min.z.min(b.pos.z));
+            max = Point3::new(max.x.max(b.pos.x), max.y.max(b.pos.y), max.z.max(b.pos.z));
+        }
+
+        let mut tree = Octree { nodes: Vec::new() };
+        if min.x > max.x { return tree; }
+
+        let center = Point3::from((min.coords + max.coords) / 2.0);
+        let half_size = ((max - min).amax() / 2.0).max(1.0);
+        tree.nodes.push(Node::new(center, half_size));
+
+        for (idx, b) in bodies.iter().enumerate() {
+            if !b.test_particle { tree.insert(bodies, idx); }
+        }
+        tree.summarise(bodies, 0);
+        tree
+    }
+
+    fn insert(&mut self, bodies: &[Body], idx: usize) {
+        let pos = bodies[idx].pos;
+        let mut node = 0;
+        let mut depth = 0;
+        loop {
+            if let Some(first) = self.nodes[node].children {
+                node = first + self.nodes[node].octant(&pos);
+                depth += 1;
+                continue;
+            }
+
+            if self.nodes[node].bodies.is_empty() || depth >= MAX_DEPTH {
+                self.nodes[node].bodies.push(idx);
+                return;
+            }
+
+            // Occupied leaf: split it and push its bodies one level down
+            self.split(node);
+            let first = self.nodes[node].children.unwrap();
+            for other in std::mem::take(&mut self.nodes[node].bodies) {
+                let child = first + self.nodes[node].octant(&bodies[other].pos);
+                self.nodes[child].bodies.push(other);
+            }
+        }
+    }
+
+    fn split(&mut self, node: usize) {
+        let (center, half_size) = (self.nodes[node].center, self.nodes[node].half_size / 2.0);
+        let first = self.nodes.len();
+        for q in 0..8 {
+            let dx = if q & 1 == 0 { -half_size } else { half_size };
+            let dy = if q & 2 == 0 { -half_size } else { half_size };
+            let dz = if q & 4 == 0 { -half_size } else { half_size };
+            self.nodes.push(Node::new(center + Vector3::new(dx, dy, dz), half_size));
+        }
+        self.nodes[node].children = Some(first);
+    }
+
+    // Fill in the mass and centre of mass of `node` and its descendants
+    fn summarise(&mut self, bodies: &[Body], node: usize) {
+        let mut mass = 0.0;
+        let mut weighted = Vector3::new(0.0, 0.0, 0.0);
+        if let Some(first) = self.nodes[node].children {
+            for child in first..first + 8 {
+                self.summarise(bodies, child);
+                let c = &self.nodes[child];
+                mass += c.mass;
+                weighted += c.mass * c.com.coords;
+            }
+        }
+        else {
+            for &idx in &self.nodes[node].bodies {
+                mass += bodies[idx].mass;
+                weighted += bodies[idx].mass * bodies[idx].pos.coords;
+            }
+        }
+
+        let n = &mut self.nodes[node];
+        n.mass = mass;
+        if mass > 0.0 {
+            n.com = Point3::from(weighted / mass);
+        }
+    }
+
+    // Acceleration of body `idx` due to every other body. A cell is opened
+    // unless its width over its distance from the body is below `theta`.
+    // Cells are softened with the global softening length
+    pub fn accel_on(&self, bodies: &[Body], idx: usize, theta: f64,
+                    softening: f64) -> Vector3<f64> {
+        let mut a = Vector3::new(0.0, 0.0, 0.0);
+        if self.nodes.is_empty() { return a; }
+
+        let pos = bodies[idx].pos;
+        let cell_eps_squared = bodies[idx].pair_softening(softening, softening * softening);
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let n = &self.nodes[node];
+            if n.mass == 0.0 { continue; }
+
+            match n.children {
+                None => {
+                    for &other in &n.bodies {
+                        if other != idx {
+                            let (b, other) = (&bodies[idx], &bodies[other]);
+                            a += b.accel_towards(other, &(other.pos - b.pos), softening);
+                        }
+                    }
+                }
+                Some(first) => {
+                    let d = (n.com - pos).norm();
+                    // Never approximate a cell containing the body itself
+                    if !n.contains(&pos) && 2.0 * n.half_size < theta * d {
+                        a += point_mass_accel(&(n.com - pos), n.mass, cell_eps_squared);
+                    }
+                    else {
+                        stack.extend(first..first + 8);
+                    }
+                }
+            }
+        }
+        a
+    }
+}
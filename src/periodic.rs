@@ -0,0 +1,192 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+// Cells along each side of the table of Ewald corrections, which covers
+// one octant of the separations allowed between nearest images
+const EWALD_CELLS: usize = 16;
+
+// Splitting between the real space and Fourier sums, in units of one over
+// the box size, and how far each sum goes
+const EWALD_ALPHA: f64 = 2.0;
+const EWALD_REAL_CELLS: i32 = 2;
+const EWALD_MAX_WAVENUMBER_SQUARED: i32 = 10;
+
+// Cubic domain centred on the global origin, repeating forever in every
+// direction. Bodies leaving through one face come back in through the
+// opposite one, and each body feels the nearest image of every other body
+// plus the Ewald sum over all the other images. As in cosmological
+// simulations, the sum is taken relative to the mean density, which
+// would otherwise pull infinitely hard
+pub struct PeriodicBox {
+    pub size: f64, // Length of each side
+    // Ewald correction for a unit box and unit G M, at the corners of a
+    // grid over [0, 1/2]^3
+    ewald: Vec<Vector3<f64>>
+}
+
+impl PeriodicBox {
+    pub fn new(size: f64) -> PeriodicBox {
+        let n = EWALD_CELLS + 1;
+        let mut ewald = Vec::with_capacity(n * n * n);
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    let x = Vector3::new(i as f64, j as f64, k as f64) / (2.0 * EWALD_CELLS as f64);
+                    ewald.push(ewald_correction(&x));
+                }
+            }
+        }
+        PeriodicBox { size, ewald }
+    }
+
+    // The image of `pos` inside the box
+    pub fn wrap(&self, pos: &Point3<f64>) -> Point3<f64> {
+        Point3::from(pos.coords.map(|x| x - self.size * (x / self.size + 0.5).floor()))
+    }
+
+    // Shortest of the separations `d` between images of two points
+    pub fn nearest_image(&self, d: &Vector3<f64>) -> Vector3<f64> {
+        d.map(|x| x - self.size * (x / self.size).round())
+    }
+
+    // Acceleration per unit G M due to all images of a mass except the
+    // nearest, which is `d` away from it (as given by `nearest_image`).
+    // Interpolated from the table, using the symmetry of the box
+    pub fn ewald_accel(&self, d: &Vector3<f64>) -> Vector3<f64> {
+        let cells = EWALD_CELLS as f64;
+        let u = d.map(|x| (x.abs() / self.size * 2.0 * cells).min(cells));
+        let corner = u.map(|x| x.floor().min(cells - 1.0));
+        let f = u - corner;
+        let (i, j, k) = (corner.x as usize, corner.y as usize, corner.z as usize);
+
+        let n = EWALD_CELLS + 1;
+        let mut a = Vector3::new(0.0, 0.0, 0.0);
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let weight = (if di == 0 { 1.0 - f.x } else { f.x })
+                        * (if dj == 0 { 1.0 - f.y } else { f.y })
+                        * (if dk == 0 { 1.0 - f.z } else { f.z });
+                    a += weight * self.ewald[((i + di) * n + j + dj) * n + k + dk];
+                }
+            }
+        }
+
+        // The correction is odd in each component of `d`
+        let sign = d.map(|x| if x < 0.0 { -1.0 } else { 1.0 });
+        a.component_mul(&sign) / (self.size * self.size)
+    }
+}
+
+// Acceleration at `x` due to a unit mass at the origin of a unit box and
+// all its images, minus the pull of the mass itself (Hernquist, Bouchet &
+// Suto 1991)
+fn ewald_correction(x: &Vector3<f64>) -> Vector3<f64> {
+    let alpha = EWALD_ALPHA;
+    let sqrt_pi = std::f64::consts::PI.sqrt();
+    let mut a = Vector3::new(0.0, 0.0, 0.0);
+
+    for nx in -EWALD_REAL_CELLS..=EWALD_REAL_CELLS {
+        for ny in -EWALD_REAL_CELLS..=EWALD_REAL_CELLS {
+            for nz in -EWALD_REAL_CELLS..=EWALD_REAL_CELLS {
+                let d = x - Vector3::new(nx as f64, ny as f64, nz as f64);
+                let r = d.norm();
+                if r == 0.0 { continue; }
+
+                let y = alpha * r;
+                let gaussian = 2.0 * y / sqrt_pi * (-y * y).exp();
+                if nx == 0 && ny == 0 && nz == 0 {
+                    // The Newtonian pull of the mass itself cancels most of
+                    // this term, so only what's left of it is added
+                    a += d / (r * r * r) * erf_minus_gaussian(y);
+                }
+                else {
+                    a -= d / (r * r * r) * (erfc(y) + gaussian);
+                }
+            }
+        }
+    }
+
+    let pi = std::f64::consts::PI;
+    let h_max = (EWALD_MAX_WAVENUMBER_SQUARED as f64).sqrt() as i32;
+    for hx in -h_max..=h_max {
+        for hy in -h_max..=h_max {
+            for hz in -h_max..=h_max {
+                let h_squared = hx * hx + hy * hy + hz * hz;
+                if h_squared == 0 || h_squared > EWALD_MAX_WAVENUMBER_SQUARED { continue; }
+
+                let h = Vector3::new(hx as f64, hy as f64, hz as f64);
+                let h_squared = h_squared as f64;
+                a -= 2.0 / h_squared * (-pi * pi * h_squared / (alpha * alpha)).exp()
+                    * (2.0 * pi * h.dot(x)).sin() * h;
+            }
+        }
+    }
+    a
+}
+
+// erf(y) - 2y/sqrt(pi) exp(-y^2), summed as a series so nothing is lost
+// when the two nearly cancel for small y
+fn erf_minus_gaussian(y: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut power = y; // (-1)^k y^(2k+1) / k!
+    for k in 1..100 {
+        power *= -y * y / k as f64;
+        let term = -power * 2.0 * k as f64 / (2 * k + 1) as f64;
+        sum += term;
+        if term.abs() <= 1e-17 * sum.abs() { break; }
+    }
+    2.0 / std::f64::consts::PI.sqrt() * sum
+}
+
+// Complementary error function for y >= 0, to a relative error of 1.2e-7
+// (Numerical Recipes' erfcc)
+fn erfc(y: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * y);
+    t * (-y * y - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418
+        + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587
+        + t * (-0.82215223 + t * 0.17087277))))))))).exp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_net_force_at_symmetric_points() {
+        let periodic = PeriodicBox::new(10.0);
+        // The images balance out at the mass itself
+        assert!(periodic.ewald_accel(&Vector3::new(0.0, 0.0, 0.0)).norm() < 1e-15);
+
+        // Halfway to the next image, along an axis and across the diagonal,
+        // the rest of the images cancel the pull of the nearest one
+        for x in [Vector3::new(5.0, 0.0, 0.0), Vector3::new(5.0, 5.0, 5.0_f64)].iter() {
+            let nearest = -x / x.norm().powi(3);
+            let total = nearest + periodic.ewald_accel(x);
+            assert!(total.norm() < 1e-5 * nearest.norm(), "{:?}", total);
+        }
+    }
+
+    #[test]
+    fn ewald_table_symmetry() {
+        let periodic = PeriodicBox::new(10.0);
+        let d = Vector3::new(1.3, -2.1, 3.7);
+        let a = periodic.ewald_accel(&d);
+        assert!(a.norm() > 0.0);
+
+        // Odd under reflection of the separation
+        assert!((periodic.ewald_accel(&-d) + a).norm() < 1e-15);
+        // Unchanged by swapping axes along with the separation
+        let swapped = periodic.ewald_accel(&Vector3::new(d.y, d.z, d.x));
+        assert!((swapped - Vector3::new(a.y, a.z, a.x)).norm() < 1e-6 * a.norm());
+    }
+
+    #[test]
+    fn wrap_and_nearest_image_stay_in_box() {
+        let periodic = PeriodicBox::new(10.0);
+        let pos = periodic.wrap(&Point3::new(12.0, -7.0, 4.0));
+        assert!((pos - Point3::new(2.0, 3.0, 4.0)).norm() < 1e-12);
+
+        let d = periodic.nearest_image(&Vector3::new(9.0, -6.0, 1.0));
+        assert!((d - Vector3::new(-1.0, 4.0, 1.0)).norm() < 1e-12);
+    }
+}
@@ -0,0 +1,125 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+use crate::body::G;
+
+// Density profile of a background potential
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    PointMass,
+    Plummer, // Softened point mass, e.g. a star cluster
+    Nfw, // Navarro-Frenk-White dark matter halo
+    Logarithmic // Flat rotation curve far out, e.g. a galaxy
+}
+
+impl Profile {
+    pub const ALL: [Profile; 4] = [
+        Profile::PointMass,
+        Profile::Plummer,
+        Profile::Nfw,
+        Profile::Logarithmic
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Profile::PointMass => "Point mass",
+            Profile::Plummer => "Plummer",
+            Profile::Nfw => "NFW halo",
+            Profile::Logarithmic => "Logarithmic"
+        }
+    }
+}
+
+// Smooth, fixed field from matter that isn't made up of bodies
+#[derive(Debug, Clone, Copy)]
+pub struct Potential {
+    pub profile: Profile,
+    pub center: Point3<f64>,
+    // Total mass. For NFW this is 4 pi rho_0 r_s^3, and for logarithmic it
+    // sets the circular speed far out to sqrt(G mass / radius)
+    pub mass: f64,
+    // Plummer radius, NFW scale radius or logarithmic core radius. Unused
+    // for point masses
+    pub radius: f64
+}
+
+impl Potential {
+    // Potential energy per unit mass at `pos`, up to a constant
+    pub fn value(&self, pos: &Point3<f64>) -> f64 {
+        let r = (pos - self.center).norm();
+        let (gm, s) = (G * self.mass, self.radius);
+        match self.profile {
+            Profile::PointMass => -gm / r,
+            Profile::Plummer => -gm / (r * r + s * s).sqrt(),
+            Profile::Nfw => if r > 0.0 { -gm * (r / s).ln_1p() / r } else { -gm / s },
+            Profile::Logarithmic => 0.5 * gm / s * (r * r + s * s).ln()
+        }
+    }
+
+    pub fn accel(&self, pos: &Point3<f64>) -> Vector3<f64> {
+        let d = pos - self.center;
+        let r_squared = d.norm_squared();
+        if r_squared == 0.0 { return Vector3::new(0.0, 0.0, 0.0); }
+
+        let r = r_squared.sqrt();
+        let (gm, s) = (G * self.mass, self.radius);
+        // Magnitude of the acceleration over r
+        let g = match self.profile {
+            Profile::PointMass => gm / (r_squared * r),
+            Profile::Plummer => {
+                let s_squared = r_squared + s * s;
+                gm / (s_squared * s_squared.sqrt())
+            }
+            Profile::Nfw => {
+                // Mass enclosed within r over the mass parameter
+                let x = r / s;
+                gm * (x.ln_1p() - x / (1.0 + x)) / (r_squared * r)
+            }
+            Profile::Logarithmic => gm / s / (r_squared + s * s)
+        };
+        -g * d
+    }
+}
+
+// `count` levels splitting the finite `values` into bands of about equal
+// size, so contours are spread over the whole view whatever the profile
+pub fn contour_levels(values: &[f64], count: usize) -> Vec<f64> {
+    let mut sorted: Vec<f64> = values.iter().cloned().filter(|v| v.is_finite()).collect();
+    if sorted.is_empty() { return Vec::new(); }
+
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    (0..count)
+        .map(|k| sorted[(k * 2 + 1) * sorted.len() / (count * 2)])
+        .collect()
+}
+
+// Line segments along which the `nx` by `ny` grid of `values` (row by
+// row) crosses `level`, in grid coordinates, found with marching squares
+pub fn contour_segments(values: &[f64], nx: usize, ny: usize,
+                        level: f64) -> Vec<[(f64, f64); 2]> {
+    let mut segments = Vec::new();
+    for j in 0..ny.saturating_sub(1) {
+        for i in 0..nx.saturating_sub(1) {
+            // Corners going around the cell
+            let corners = [(i, j), (i + 1, j), (i + 1, j + 1), (i, j + 1)];
+            let v: Vec<f64> = corners.iter().map(|&(x, y)| values[y * nx + x]).collect();
+            if v.iter().any(|v| !v.is_finite()) { continue; }
+
+            let mut crossings = Vec::with_capacity(4);
+            for k in 0..4 {
+                let (v0, v1) = (v[k], v[(k + 1) % 4]);
+                if (v0 > level) != (v1 > level) {
+                    let t = (level - v0) / (v1 - v0);
+                    let (p0, p1) = (corners[k], corners[(k + 1) % 4]);
+                    crossings.push((p0.0 as f64 + t * (p1.0 as f64 - p0.0 as f64),
+                                    p0.1 as f64 + t * (p1.1 as f64 - p0.1 as f64)));
+                }
+            }
+            for pair in crossings.chunks(2) {
+                if let [p0, p1] = pair {
+                    segments.push([*p0, *p1]);
+                }
+            }
+        }
+    }
+    segments
+}
@@ -0,0 +1,109 @@
+use ggez::nalgebra::Vector3;
+
+use crate::body::Body;
+use crate::forces::Forces;
+use crate::integrator::{adaptive_advance, Attempt, IntegratorState};
+
+// Dormand-Prince 5(4) tableau. The 5th order solution is propagated and
+// the difference to the embedded 4th order one is used as error estimate
+const A: [[f64; 6]; 7] = [
+    [0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [1.0/5.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+    [3.0/40.0, 9.0/40.0, 0.0, 0.0, 0.0, 0.0],
+    [44.0/45.0, -56.0/15.0, 32.0/9.0, 0.0, 0.0, 0.0],
+    [19372.0/6561.0, -25360.0/2187.0, 64448.0/6561.0, -212.0/729.0, 0.0, 0.0],
+    [9017.0/3168.0, -355.0/33.0, 46732.0/5247.0, 49.0/176.0, -5103.0/18656.0, 0.0],
+    [35.0/384.0, 0.0, 500.0/1113.0, 125.0/192.0, -2187.0/6784.0, 11.0/84.0]
+];
+const B: [f64; 7] = [35.0/384.0, 0.0, 500.0/1113.0, 125.0/192.0,
+                     -2187.0/6784.0, 11.0/84.0, 0.0];
+const B_STAR: [f64; 7] = [5179.0/57600.0, 0.0, 7571.0/16695.0, 393.0/640.0,
+                          -92097.0/339200.0, 187.0/2100.0, 1.0/40.0];
+
+// Fraction of the step size the error estimate allows that is taken
+const SAFETY: f64 = 0.9;
+
+// Derivatives of positions and velocities at one stage
+struct Stage {
+    dpos: Vec<Vector3<f64>>,
+    dv: Vec<Vector3<f64>>
+}
+
+fn eval_stage(bodies: &mut [Body], forces: &Forces) -> Stage {
+    forces.update_accels(bodies);
+    Stage {
+        dpos: bodies.iter().map(|b| b.v).collect(),
+        dv: bodies.iter().map(|b| b.a).collect()
+    }
+}
+
+// Advance `bodies` by `duration` seconds (negative to go backwards),
+// taking as many substeps as needed to meet the tolerances in `state`
+pub fn advance(bodies: &mut [Body], duration: f64, state: &mut IntegratorState,
+               forces: &Forces) -> f64 {
+    if bodies.is_empty() { return duration; }
+    adaptive_advance(duration, state, |h, state| {
+        let (trial, err) = try_step(bodies, h, state, forces);
+        let accepted = err <= 1.0;
+        if accepted { bodies.clone_from_slice(&trial); }
+        Attempt { accepted, factor: SAFETY * err.powf(-0.2) }
+    })
+}
+
+// Returns the 5th order solution and the scaled error norm of the step
+fn try_step(bodies: &[Body], h: f64, state: &IntegratorState,
+            forces: &Forces) -> (Vec<Body>, f64) {
+    let mut stages: Vec<Stage> = Vec::with_capacity(7);
+    let mut tmp = bodies.to_vec();
+    for a in A.iter() {
+        for (i, b) in tmp.iter_mut().enumerate() {
+            b.pos = bodies[i].pos;
+            b.v = bodies[i].v;
+            for (j, k) in stages.iter().enumerate() {
+                b.pos += h * a[j] * k.dpos[i];
+                b.v += h * a[j] * k.dv[i];
+            }
+        }
+        stages.push(eval_stage(&mut tmp, forces));
+    }
+
+    // The last stage is evaluated at the 5th order solution, so `tmp`
+    // already holds the result of the step
+    let mut sum = 0.0;
+    for (i, b) in tmp.iter().enumerate() {
+        let mut err_pos = Vector3::new(0.0, 0.0, 0.0);
+        let mut err_v = Vector3::new(0.0, 0.0, 0.0);
+        for (s, k) in stages.iter().enumerate() {
+            err_pos += h * (B[s] - B_STAR[s]) * k.dpos[i];
+            err_v += h * (B[s] - B_STAR[s]) * k.dv[i];
+        }
+
+        let old = &bodies[i];
+        let scale_pos = state.atol + state.rtol * old.pos.coords.norm().max(b.pos.coords.norm());
+        let scale_v = state.atol + state.rtol * old.v.norm().max(b.v.norm());
+        sum += (err_pos.norm() / scale_pos).powi(2) + (err_v.norm() / scale_v).powi(2);
+    }
+    let err = (sum / (2 * bodies.len()) as f64).sqrt();
+
+    (tmp, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integrator::tests::{energy, sun_earth};
+
+    #[test]
+    fn two_body_energy_drift() {
+        let mut bodies = sun_earth();
+        let forces = Forces::default();
+        let mut state = IntegratorState::default();
+        let e0 = energy(&bodies);
+
+        // Ten years, in steps of a frame each
+        for _ in 0..300 {
+            assert_eq!(advance(&mut bodies, 1e6, &mut state, &forces), 1e6);
+        }
+        assert!(((energy(&bodies) - e0) / e0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,88 @@
+use ggez::nalgebra::Vector3;
+
+// A planned burn of the engine
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Maneuver {
+    pub time: f64, // Simulated seconds at which to start
+    // Thrust direction as (prograde, radial, normal) components, relative
+    // to the body being orbited, with radial pointing away from it and
+    // normal along the angular momentum
+    pub direction: Vector3<f64>,
+    pub delta_v: f64
+}
+
+// Engine and flight plan of a body that can manoeuvre
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spacecraft {
+    pub thrust: f64, // Acceleration while the engine is on, in m/s^2
+    pub budget: f64, // Total delta-v available, in m/s
+    pub maneuvers: Vec<Maneuver>, // Sorted by time
+    // Direction of the burn under way, kept up to date by `update_bodies`
+    pub burn: Option<Vector3<f64>>
+}
+
+impl Default for Spacecraft {
+    fn default() -> Spacecraft {
+        Spacecraft {
+            thrust: 1.0,
+            budget: 5000.0,
+            maneuvers: Vec::new(),
+            burn: None
+        }
+    }
+}
+
+impl Spacecraft {
+    // Put the maneuvers back in order of time after editing them
+    pub fn sort(&mut self) {
+        self.maneuvers.sort_by(|m, m_| {
+            m.time.partial_cmp(&m_.time).unwrap_or(std::cmp::Ordering::Equal)
+        });
+    }
+
+    // When each maneuver actually burns, as (start, end, direction). The
+    // engine does one burn at a time, so a maneuver planned before the
+    // previous one is over waits for it, and burns are cut short once the
+    // budget runs out
+    pub fn schedule(&self) -> Vec<(f64, f64, Vector3<f64>)> {
+        let mut burns = Vec::new();
+        if self.thrust <= 0.0 { return burns; }
+
+        let mut end = f64::NEG_INFINITY;
+        let mut remaining = self.budget;
+        for m in &self.maneuvers {
+            let delta_v = m.delta_v.min(remaining);
+            if delta_v <= 0.0 || m.direction.norm_squared() == 0.0 { continue; }
+
+            let start = m.time.max(end);
+            end = start + delta_v / self.thrust;
+            remaining -= delta_v;
+            burns.push((start, end, m.direction.normalize()));
+        }
+        burns
+    }
+
+    // Direction of the burn going on at `time`, if any
+    pub fn burn_at(&self, time: f64) -> Option<Vector3<f64>> {
+        self.schedule().into_iter()
+            .find(|&(start, end, _)| start <= time && time < end)
+            .map(|(_, _, direction)| direction)
+    }
+
+    // Delta-v left at `time`
+    pub fn remaining(&self, time: f64) -> f64 {
+        let used: f64 = self.schedule().iter()
+            .map(|&(start, end, _)| (time.max(start).min(end) - start) * self.thrust)
+            .sum();
+        self.budget - used
+    }
+
+    // Times strictly between `t0` and `t1` at which a burn starts or ends
+    pub fn switches(&self, t0: f64, t1: f64) -> Vec<f64> {
+        let (lo, hi) = (t0.min(t1), t0.max(t1));
+        self.schedule().iter()
+            .flat_map(|&(start, end, _)| vec![start, end])
+            .filter(|&t| lo < t && t < hi)
+            .collect()
+    }
+}
@@ -1,20 +1,56 @@
-use ggez::nalgebra::Point2;
+use ggez::nalgebra::{Point2, Point3};
 
 use crate::body::Body;
+use crate::camera::Camera;
+use crate::frame::Frame;
+use crate::integrator::{Integrator, IntegratorState};
+use crate::forces::{Forces, Solver};
+use crate::potential::Potential;
+use crate::collision::CollisionMode;
+use crate::history::History;
 use crate::ui::UiWrapper;
 
 pub struct GameState {
     pub size: (f32, f32), // (width, height)
 
-    pub origin: Point2<f32>, // Position of center on global xy-plane
-    pub scale: f32, // 1 pixel corresponds to `scale` units on global xy-plane
+    pub origin: Point3<f64>, // Position shown at the center of the screen, in `frame`
+    pub scale: f64, // 1 pixel corresponds to `scale` global units
+    pub camera: Camera,
+    pub frame: Frame, // Reference frame the bodies are shown in
     pub bodies: Vec<Body>,
 
-    pub dt: f32, // Number of seconds that pass in a step
+    pub dt: f64, // Number of seconds that pass in a step (ignored by adaptive integrators)
+    pub speed: f64, // Simulated seconds per real second
+    pub time: f64, // Simulated seconds since the start
+    pub accumulator: f64, // Simulated seconds not yet stepped through
+    pub lagging: bool, // Whether the last frame fell short of the time it was meant to cover
+    pub integrator: Integrator,
+    pub integration: IntegratorState,
+    pub forces: Forces,
+    pub collisions: CollisionMode,
+    pub restitution: f64, // Fraction of approach speed kept after a bounce
+    pub history: History<StepSettings>,
+    pub show_contours: bool, // Draw contours of the background potentials
     pub paused: bool,
     pub reversed: bool
 }
 
+// Everything besides the bodies that decides where a step takes them, so
+// rewinding can redo recorded steps the way they were first taken
+#[derive(Clone)]
+pub struct StepSettings {
+    pub integrator: Integrator,
+    pub integration: IntegratorState,
+    pub collisions: CollisionMode,
+    pub restitution: f64,
+    pub solver: Solver,
+    pub theta: f64,
+    pub softening: f64,
+    pub enabled: Vec<bool>, // Whether each force model is enabled
+    pub potentials: Vec<Potential>,
+    pub box_size: Option<f64> // Side of the periodic domain, if any
+}
+
 // Proxy through which ggez and ImGui communicate with each other
 pub struct UiState {
     pub mouse_pos: Point2<f32>,
@@ -24,12 +60,31 @@ pub struct UiState {
     // Main menu
     pub input_scale: f32,
     pub input_dt: f32,
+    pub input_speed: f32,
+    pub input_rtol_exp: i32,
+    pub input_atol_exp: i32,
+    pub input_eta: f32,
+    pub input_theta: f32,
+    pub solver_error: Option<f64>, // Last measured Barnes-Hut error
+    pub input_softening: f32,
+    pub input_threads: i32,
+    pub input_box_size: f32, // Side of the periodic domain, to scale
+    pub input_frame_pair: [i32; 2], // Indices of the bodies to co-rotate with
+    pub input_restitution: f32,
+    pub input_ring_count: i32,
+    pub input_ring_radii: [f32; 2], // Inner and outer radius, to scale
+    pub input_ring_beta: f32,
 
     // Edit body dialog
     pub selected_body_idx: Option<usize>,
     pub input_mass: f32,
-    pub input_pos: [f32; 2],
-    pub input_v: [f32; 2],
+    pub input_pos: [f32; 3],
+    pub input_v: [f32; 3],
+    pub input_radius: f32,
+    pub input_body_softening: f32,
+    pub input_luminosity: f32, // In Suns
+    pub input_beta: f32,
+    pub input_atmosphere: [f32; 2], // Surface density and scale height
     pub input_color: [f32; 4]
 }
 
@@ -1,7 +1,7 @@
 use std::time::Instant;
 
 use ggez;
-use ggez::nalgebra::{Point2, Vector2};
+use ggez::nalgebra::{Point3, Vector3};
 use ggez::event::{KeyCode, KeyMods, MouseButton};
 use ggez::graphics;
 
@@ -13,6 +13,17 @@ use imgui::*;
 use imgui_gfx_renderer::*;
 
 use crate::state::*;
+use crate::camera::Camera;
+use crate::frame::Frame;
+use crate::body::{Atmosphere, L_SUN};
+use crate::integrator::Integrator;
+use crate::forces::Solver;
+use crate::collision::CollisionMode;
+use crate::potential::{Potential, Profile};
+use crate::periodic::PeriodicBox;
+use crate::spacecraft::{Spacecraft, Maneuver};
+
+const DAY: f64 = 86400.0; // Burn times are entered in days
 
 #[derive(Default)]
 struct MouseState {
@@ -68,8 +79,21 @@ fn build_main_menu(ui: &Ui, game_state: &mut GameState,
             ui.menu(im_str!("PAUSED"), false, || {});
         }
 
+        if game_state.lagging && !game_state.paused {
+            ui.menu(im_str!("LAGGING"), false, || {});
+        }
+
         if game_state.reversed {
-            ui.menu(im_str!("REVERSED"), false, || {});
+            // Once recorded steps run out, bodies are integrated backwards
+            let reversed_text = if game_state.history.is_empty() {
+                "REVERSED (integrating)\0".to_string()
+            } else {
+                format!("REVERSED ({} steps recorded)\0", game_state.history.len())
+            };
+            let s = unsafe {
+                ImStr::from_utf8_with_nul_unchecked(reversed_text.as_bytes())
+            };
+            ui.menu(s, false, || {});
         }
 
         let scale_text = format!("Scale: {:e}x\0", game_state.scale);
@@ -80,27 +104,425 @@ fn build_main_menu(ui: &Ui, game_state: &mut GameState,
             let input_scale = ui.input_float(im_str!(""), &mut ui_state.input_scale)
                 .enter_returns_true(true);
             if input_scale.build() {
-                game_state.scale = ui_state.input_scale;
+                game_state.scale = ui_state.input_scale as f64;
+            }
+        });
+
+        let camera = &mut game_state.camera;
+        let view_text = if camera.yaw == 0.0 && camera.pitch == 0.0 {
+            "View: top-down\0".to_string()
+        } else {
+            format!("View: yaw {:.0}, pitch {:.0}\0", camera.yaw.to_degrees(),
+                    camera.pitch.to_degrees())
+        };
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(view_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            let mut angles = [camera.yaw.to_degrees() as f32, camera.pitch.to_degrees() as f32];
+            if ui.input_float2(im_str!("Yaw, pitch (degrees)"), &mut angles)
+                .enter_returns_true(true)
+                .build() {
+                camera.yaw = (angles[0] as f64).to_radians();
+                camera.pitch = (angles[1] as f64).to_radians()
+                    .clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+            }
+
+            if ui.button(im_str!("Top-down"), [100.0, 20.0]) {
+                *camera = Camera::default();
+            }
+            ui.text("Drag with the right mouse button to turn");
+        });
+
+        let frame_text = format!("Frame: {}\0", game_state.frame.name());
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(frame_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            let mut frame = game_state.frame;
+            if MenuItem::new(im_str!("Inertial"))
+                .selected(frame == Frame::Inertial)
+                .build(ui) {
+                frame = Frame::Inertial;
+            }
+
+            // Sun-Jupiter and the like: turn with the selected body and
+            // the heaviest one
+            let heaviest = (0..game_state.bodies.len()).max_by(|&idx, &idx_| {
+                let (m, m_) = (game_state.bodies[idx].mass, game_state.bodies[idx_].mass);
+                m.partial_cmp(&m_).unwrap_or(std::cmp::Ordering::Equal)
+            });
+            let pair = match (heaviest, ui_state.selected_body_idx) {
+                (Some(first), Some(second)) if first != second => Some((first, second)),
+                _ => None
+            };
+            let pair_text = match game_state.frame {
+                Frame::CoRotating(..) => game_state.frame.name(),
+                Frame::Inertial => "Co-rotating: heaviest and selected body".to_string()
+            };
+            if MenuItem::new(&ImString::new(pair_text))
+                .selected(frame != Frame::Inertial)
+                .enabled(pair.is_some())
+                .build(ui) {
+                if let Some((first, second)) = pair {
+                    frame = Frame::CoRotating(first, second);
+                    ui_state.input_frame_pair = [first as i32, second as i32];
+                }
+            }
+
+            // Or any two bodies, by index
+            ui.separator();
+            let input_pair = ui.input_int2(im_str!("Co-rotating pair"),
+                                           &mut ui_state.input_frame_pair)
+                .enter_returns_true(true);
+            if input_pair.build() {
+                let [first, second] = ui_state.input_frame_pair;
+                let count = game_state.bodies.len() as i32;
+                if first != second && (0..count).contains(&first) && (0..count).contains(&second) {
+                    frame = Frame::CoRotating(first as usize, second as usize);
+                }
+            }
+
+            // Keep the same point at the centre of the screen
+            if frame != game_state.frame {
+                let old = game_state.frame.transform(&game_state.bodies);
+                let new = frame.transform(&game_state.bodies);
+                game_state.origin = new.to_frame(&old.to_global(&game_state.origin));
+                game_state.frame = frame;
             }
         });
 
-        let dt_text = format!("Speed: {:e}x\0", game_state.dt);
+        let speed_text = format!("Speed: {:e}x\0", game_state.speed);
         let s = unsafe {
-            ImStr::from_utf8_with_nul_unchecked(dt_text.as_bytes())
+            ImStr::from_utf8_with_nul_unchecked(speed_text.as_bytes())
         };
         ui.menu(&s, true, || {
-            let input_dt = ui.input_float(im_str!(""), &mut ui_state.input_dt)
+            let input_speed = ui.input_float(im_str!(""), &mut ui_state.input_speed)
+                .enter_returns_true(true);
+            if input_speed.build() {
+                game_state.speed = ui_state.input_speed as f64;
+            }
+        });
+
+        if !game_state.integrator.is_adaptive() {
+            let dt_text = format!("Step: {:e}s\0", game_state.dt);
+            let s = unsafe {
+                ImStr::from_utf8_with_nul_unchecked(dt_text.as_bytes())
+            };
+            ui.menu(s, true, || {
+                let input_dt = ui.input_float(im_str!(""), &mut ui_state.input_dt)
+                    .enter_returns_true(true);
+                if input_dt.build() {
+                    game_state.dt = ui_state.input_dt as f64;
+                }
+            });
+        }
+
+        let integrator_text = format!("Integrator: {}\0", game_state.integrator.name());
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(integrator_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            for integrator in Integrator::ALL.iter() {
+                let selected = game_state.integrator == *integrator;
+                if MenuItem::new(&ImString::new(integrator.name()))
+                    .selected(selected)
+                    .build(ui) {
+                    game_state.integrator = *integrator;
+                }
+            }
+
+            if game_state.integrator == Integrator::WisdomHolman {
+                ui.separator();
+                let central = &mut game_state.integration.central_body;
+                if MenuItem::new(im_str!("Central body: most massive"))
+                    .selected(central.is_none())
+                    .build(ui) {
+                    *central = None;
+                }
+
+                let selected_text = match *central {
+                    Some(idx) => format!("Central body: #{}", idx),
+                    None => "Central body: selected body".to_string()
+                };
+                if MenuItem::new(&ImString::new(selected_text))
+                    .selected(central.is_some())
+                    .enabled(ui_state.selected_body_idx.is_some())
+                    .build(ui) {
+                    *central = ui_state.selected_body_idx;
+                }
+            }
+            else if game_state.integrator == Integrator::Hermite {
+                ui.separator();
+                let eta = ui.input_float(im_str!("Accuracy parameter"), &mut ui_state.input_eta)
+                    .enter_returns_true(true);
+                if eta.build() {
+                    ui_state.input_eta = ui_state.input_eta.max(1e-6);
+                    game_state.integration.eta = ui_state.input_eta as f64;
+                }
+                ui.text("Hermite always uses the direct sum");
+            }
+            else if game_state.integrator == Integrator::Ias15 {
+                ui.separator();
+                ui.text("Steps adapt to keep errors at round-off level");
+            }
+            else if game_state.integrator.is_adaptive() {
+                ui.separator();
+                let rtol = ui.input_int(im_str!("Rel. tolerance (10^x)"),
+                                        &mut ui_state.input_rtol_exp)
+                    .enter_returns_true(true);
+                if rtol.build() {
+                    game_state.integration.rtol = 10.0_f64.powi(ui_state.input_rtol_exp);
+                }
+
+                let atol = ui.input_int(im_str!("Abs. tolerance (10^x)"),
+                                        &mut ui_state.input_atol_exp)
+                    .enter_returns_true(true);
+                if atol.build() {
+                    game_state.integration.atol = 10.0_f64.powi(ui_state.input_atol_exp);
+                }
+            }
+        });
+
+        let solver_text = format!("Solver: {}\0", game_state.forces.solver.name());
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(solver_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            for solver in Solver::ALL.iter() {
+                let selected = game_state.forces.solver == *solver;
+                if MenuItem::new(&ImString::new(solver.name()))
+                    .selected(selected)
+                    .build(ui) {
+                    game_state.forces.solver = *solver;
+                    ui_state.solver_error = None;
+                }
+            }
+
+            if game_state.forces.solver == Solver::BarnesHut {
+                ui.separator();
+                let theta = ui.input_float(im_str!("Opening angle"), &mut ui_state.input_theta)
+                    .enter_returns_true(true);
+                if theta.build() {
+                    game_state.forces.theta = ui_state.input_theta.max(0.0) as f64;
+                    ui_state.solver_error = None;
+                }
+
+                if ui.button(im_str!("Compare with direct sum"), [200.0, 20.0]) {
+                    ui_state.solver_error =
+                        Some(game_state.forces.relative_error(&game_state.bodies));
+                }
+                if let Some(err) = ui_state.solver_error {
+                    ui.text(format!("Mean relative error: {:e}", err));
+                }
+            }
+
+            ui.separator();
+            let softening = ui.input_float(im_str!("Softening (km)"), &mut ui_state.input_softening)
                 .enter_returns_true(true);
-            if input_dt.build() {
-                game_state.dt = ui_state.input_dt;
+            if softening.build() {
+                game_state.forces.softening = ui_state.input_softening.max(0.0) as f64 * 1000.0;
+                ui_state.solver_error = None;
+            }
+
+            let threads = ui.input_int(im_str!("Threads"), &mut ui_state.input_threads)
+                .enter_returns_true(true);
+            if threads.build() {
+                ui_state.input_threads = ui_state.input_threads.max(1);
+                game_state.forces.threads = ui_state.input_threads as usize;
+            }
+
+            ui.separator();
+            let mut periodic = game_state.forces.periodic.is_some();
+            if ui.checkbox(im_str!("Periodic box"), &mut periodic) {
+                game_state.forces.periodic = if periodic {
+                    let size = ui_state.input_box_size.max(1.0) as f64 * game_state.scale;
+                    Some(PeriodicBox::new(size))
+                } else {
+                    None
+                };
+                ui_state.solver_error = None;
+            }
+            if let Some(periodic) = game_state.forces.periodic.as_mut() {
+                let size = ui.input_float(im_str!("Box size (to scale)"),
+                                          &mut ui_state.input_box_size)
+                    .enter_returns_true(true);
+                if size.build() {
+                    ui_state.input_box_size = ui_state.input_box_size.max(1.0);
+                    periodic.size = ui_state.input_box_size as f64 * game_state.scale;
+                }
+                if game_state.forces.solver == Solver::BarnesHut {
+                    ui.text("Periodic boxes always use the direct sum");
+                }
+            }
+
+            ui.separator();
+            for (model, enabled) in game_state.forces.models.iter_mut() {
+                if MenuItem::new(&ImString::new(model.name()))
+                    .selected(*enabled)
+                    .build(ui) {
+                    *enabled = !*enabled;
+                }
+            }
+        });
+
+        let collisions_text = format!("Collisions: {}\0", game_state.collisions.name());
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(collisions_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            for mode in CollisionMode::ALL.iter() {
+                let selected = game_state.collisions == *mode;
+                if MenuItem::new(&ImString::new(mode.name()))
+                    .selected(selected)
+                    .build(ui) {
+                    game_state.collisions = *mode;
+                }
+            }
+
+            if game_state.collisions == CollisionMode::Bounce {
+                ui.separator();
+                let restitution = ui.input_float(im_str!("Restitution"),
+                                                 &mut ui_state.input_restitution)
+                    .enter_returns_true(true);
+                if restitution.build() {
+                    ui_state.input_restitution = ui_state.input_restitution.clamp(0.0, 1.0);
+                    game_state.restitution = ui_state.input_restitution as f64;
+                }
+            }
+        });
+
+        let potentials_text = format!("Potentials: {}\0", game_state.forces.potentials.len());
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(potentials_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            let scale = game_state.scale;
+            let mut removed = None;
+            for (idx, p) in game_state.forces.potentials.iter_mut().enumerate() {
+                // Keep the ID fixed so the menu stays open when the profile changes
+                let label = ImString::new(format!("{}: {}###potential{}", idx + 1,
+                                                 p.profile.name(), idx));
+                ui.menu(&label, true, || {
+                    for profile in Profile::ALL.iter() {
+                        if MenuItem::new(&ImString::new(profile.name()))
+                            .selected(p.profile == *profile)
+                            .build(ui) {
+                            p.profile = *profile;
+                        }
+                    }
+                    ui.separator();
+
+                    let mut center = [(p.center.x / scale) as f32, (p.center.y / scale) as f32,
+                                      (p.center.z / scale) as f32];
+                    if ui.input_float3(im_str!("Centre (to scale)"), &mut center)
+                        .enter_returns_true(true)
+                        .build() {
+                        p.center = scale * Point3::new(center[0] as f64, center[1] as f64,
+                                                       center[2] as f64);
+                    }
+
+                    let mut mass = (p.mass / 1e+22_f64) as f32;
+                    if ui.input_float(im_str!("Mass (10^22kg)"), &mut mass)
+                        .enter_returns_true(true)
+                        .build() {
+                        p.mass = mass as f64 * 1e+22_f64;
+                    }
+
+                    // Radii must be positive, as NFW and logarithmic
+                    // potentials divide by them
+                    let mut radius = (p.radius / scale) as f32;
+                    if ui.input_float(im_str!("Radius (to scale)"), &mut radius)
+                        .enter_returns_true(true)
+                        .build() {
+                        p.radius = (radius as f64 * scale).max(1.0);
+                    }
+
+                    if ui.button(im_str!("Remove"), [100.0, 20.0]) {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                game_state.forces.potentials.remove(idx);
+            }
+
+            if ui.button(im_str!("Add at centre of view"), [250.0, 20.0]) {
+                game_state.forces.potentials.push(Potential {
+                    profile: Profile::Plummer,
+                    center: game_state.frame.transform(&game_state.bodies)
+                        .to_global(&game_state.origin),
+                    mass: 1.989e+30_f64 * 1e+6_f64, // A million Suns
+                    radius: 50.0 * scale
+                });
+            }
+
+            if MenuItem::new(im_str!("Show contours"))
+                .selected(game_state.show_contours)
+                .build(ui) {
+                game_state.show_contours = !game_state.show_contours;
+            }
+        });
+
+        let particles = game_state.bodies.iter().filter(|b| b.test_particle).count();
+        let particles_text = format!("Test particles: {}\0", particles);
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(particles_text.as_bytes())
+        };
+        ui.menu(s, true, || {
+            ui.input_int(im_str!("Count"), &mut ui_state.input_ring_count).build();
+            ui.input_float2(im_str!("Radii (to scale)"), &mut ui_state.input_ring_radii).build();
+            ui.input_float(im_str!("Beta"), &mut ui_state.input_ring_beta).build();
+
+            // Rings go around the selected body, on circular orbits
+            if let Some(idx) = ui_state.selected_body_idx {
+                if ui.button(im_str!("Add ring around selected body"), [250.0, 20.0]) {
+                    let count = ui_state.input_ring_count.max(0) as usize;
+                    let [inner, outer] = ui_state.input_ring_radii;
+                    let (inner, outer) = (inner.max(0.0) as f64, outer.max(0.0) as f64);
+                    game_state.add_ring(idx, count, inner * game_state.scale,
+                                        outer.max(inner) * game_state.scale,
+                                        ui_state.input_ring_beta as f64);
+                }
+            }
+            else {
+                ui.text("Select a body to add a ring around it");
+            }
+
+            if particles > 0 && ui.button(im_str!("Remove all"), [250.0, 20.0]) {
+                // Indices past the end may be left over from rewinding, and
+                // are dropped like those of removed particles
+                let new_idx = game_state.remove_test_particles();
+                let remap = |idx: usize| new_idx.get(idx).copied().flatten();
+                ui_state.selected_body_idx = ui_state.selected_body_idx.and_then(remap);
+                let central = &mut game_state.integration.central_body;
+                *central = central.and_then(remap);
+                game_state.frame = game_state.frame.reindex(remap);
             }
         });
 
+        let time_text = format!("Day {:.2}\0", game_state.time / DAY);
+        let s = unsafe {
+            ImStr::from_utf8_with_nul_unchecked(time_text.as_bytes())
+        };
+        ui.menu(s, false, || {});
+
         let fps_text = format!("FPS: {:.0}\0", fps);
         let s = unsafe {
             ImStr::from_utf8_with_nul_unchecked(fps_text.as_bytes())
         };
         ui.menu(&s, false, || {});
+
+        if game_state.integrator.is_adaptive() {
+            let steps_text = format!("Steps: {} ok, {} rejected, {:.3e} s\0",
+                                     game_state.integration.accepted,
+                                     game_state.integration.rejected,
+                                     game_state.integration.h);
+            let s = unsafe {
+                ImStr::from_utf8_with_nul_unchecked(steps_text.as_bytes())
+            };
+            ui.menu(s, false, || {});
+        }
     });
     token.pop(&ui);
 }
@@ -114,38 +536,150 @@ fn build_body_ui(ui: &Ui, game_state: &mut GameState,
         .resizable(false)
         .collapsible(false)
         .build(ui, || {
-            let body = game_state.bodies[body_idx];
-            ui_state.input_mass = body.mass / 1e+22_f32;
-            ui_state.input_pos = [body.pos.x / game_state.scale,
-                                body.pos.y / game_state.scale];
-            ui_state.input_v = [body.v.x / 1000.0, body.v.y / 1000.0];
+            let body = game_state.bodies[body_idx].clone();
+            ui_state.input_mass = (body.mass / 1e+22_f64) as f32;
+            ui_state.input_pos = [(body.pos.x / game_state.scale) as f32,
+                                (body.pos.y / game_state.scale) as f32,
+                                (body.pos.z / game_state.scale) as f32];
+            ui_state.input_v = [(body.v.x / 1000.0) as f32, (body.v.y / 1000.0) as f32,
+                                (body.v.z / 1000.0) as f32];
+            ui_state.input_radius = (body.radius / 1000.0) as f32;
+            ui_state.input_body_softening =
+                (body.softening.unwrap_or(game_state.forces.softening) / 1000.0) as f32;
+            ui_state.input_luminosity = (body.luminosity / L_SUN) as f32;
+            ui_state.input_beta = body.beta as f32;
+            if let Some(atmosphere) = body.atmosphere {
+                ui_state.input_atmosphere = [atmosphere.density as f32,
+                                             (atmosphere.scale_height / 1000.0) as f32];
+            }
             ui_state.input_color.clone_from_slice(&body.color);
 
             // Update position fields accordingly when scale is changed
             if ui_state.scale_change != 1.0 {
                 ui_state.input_pos = [ui_state.input_pos[0] / ui_state.scale_change,
-                                    ui_state.input_pos[1] / ui_state.scale_change];
+                                    ui_state.input_pos[1] / ui_state.scale_change,
+                                    ui_state.input_pos[2] / ui_state.scale_change];
                 ui_state.scale_change = 1.0;
             }
 
-            let mass = ui.input_float(im_str!("Mass (10^22kg)"), &mut ui_state.input_mass)
-                .enter_returns_true(true);
-            if mass.build() {
-                game_state.bodies[body_idx].mass = ui_state.input_mass * 1e+22_f32;
+            let mut test_particle = body.test_particle;
+            if ui.checkbox(im_str!("Test particle"), &mut test_particle) {
+                game_state.bodies[body_idx].test_particle = test_particle;
+                if test_particle {
+                    game_state.bodies[body_idx].mass = 0.0;
+                }
+            }
+
+            // Test particles are massless
+            if !test_particle {
+                let mass = ui.input_float(im_str!("Mass (10^22kg)"), &mut ui_state.input_mass)
+                    .enter_returns_true(true);
+                if mass.build() {
+                    game_state.bodies[body_idx].mass = ui_state.input_mass as f64 * 1e+22_f64;
+                }
             }
 
-            let pos = ui.input_float2(im_str!("Pos (to scale)"), &mut ui_state.input_pos)
+            let pos = ui.input_float3(im_str!("Pos (to scale)"), &mut ui_state.input_pos)
                 .enter_returns_true(true);
             if pos.build() {
+                let [x, y, z] = ui_state.input_pos;
                 game_state.bodies[body_idx].pos = game_state.scale *
-                    Point2::new(ui_state.input_pos[0], ui_state.input_pos[1]);
+                    Point3::new(x as f64, y as f64, z as f64);
             }
 
-            let v = ui.input_float2(im_str!("Velocity (km/s)"), &mut ui_state.input_v)
+            let v = ui.input_float3(im_str!("Velocity (km/s)"), &mut ui_state.input_v)
                 .enter_returns_true(true);
             if v.build() {
-                game_state.bodies[body_idx].v = 1000.0 *
-                    Vector2::new(ui_state.input_v[0], ui_state.input_v[1]);
+                let [x, y, z] = ui_state.input_v;
+                game_state.bodies[body_idx].v = 1000.0 * Vector3::new(x as f64, y as f64, z as f64);
+            }
+
+            let mut pinned = body.pinned;
+            if ui.checkbox(im_str!("Pinned"), &mut pinned) {
+                game_state.bodies[body_idx].pinned = pinned;
+                if pinned {
+                    game_state.bodies[body_idx].v = Vector3::new(0.0, 0.0, 0.0);
+                }
+            }
+
+            let radius = ui.input_float(im_str!("Radius (km)"), &mut ui_state.input_radius)
+                .enter_returns_true(true);
+            if radius.build() {
+                game_state.bodies[body_idx].radius = ui_state.input_radius.max(0.0) as f64 * 1000.0;
+            }
+
+            let mut custom_softening = body.softening.is_some();
+            if ui.checkbox(im_str!("Custom softening"), &mut custom_softening) {
+                game_state.bodies[body_idx].softening = if custom_softening {
+                    Some(game_state.forces.softening)
+                } else {
+                    None
+                };
+            }
+            if custom_softening {
+                let softening = ui.input_float(im_str!("Softening (km)"),
+                                               &mut ui_state.input_body_softening)
+                    .enter_returns_true(true);
+                if softening.build() {
+                    game_state.bodies[body_idx].softening =
+                        Some(ui_state.input_body_softening.max(0.0) as f64 * 1000.0);
+                }
+            }
+
+            // Test particles give off no light, as they can't push on anything
+            if !test_particle {
+                let luminosity = ui.input_float(im_str!("Luminosity (Suns)"),
+                                                &mut ui_state.input_luminosity)
+                    .enter_returns_true(true);
+                if luminosity.build() {
+                    game_state.bodies[body_idx].luminosity =
+                        ui_state.input_luminosity.max(0.0) as f64 * L_SUN;
+                }
+            }
+
+            let beta = ui.input_float(im_str!("Beta (radiation/gravity)"), &mut ui_state.input_beta)
+                .enter_returns_true(true);
+            if beta.build() {
+                game_state.bodies[body_idx].beta = ui_state.input_beta as f64;
+            }
+
+            // Defaults to Earth's atmosphere
+            let mut has_atmosphere = body.atmosphere.is_some();
+            if ui.checkbox(im_str!("Atmosphere"), &mut has_atmosphere) {
+                game_state.bodies[body_idx].atmosphere = if has_atmosphere {
+                    Some(Atmosphere { density: 1.225, scale_height: 8500.0 })
+                } else {
+                    None
+                };
+            }
+            if has_atmosphere {
+                let density = ui.input_float(im_str!("Surface density (kg/m^3)"),
+                                             &mut ui_state.input_atmosphere[0])
+                    .enter_returns_true(true)
+                    .build();
+                let scale_height = ui.input_float(im_str!("Scale height (km)"),
+                                                  &mut ui_state.input_atmosphere[1])
+                    .enter_returns_true(true)
+                    .build();
+                if density || scale_height {
+                    game_state.bodies[body_idx].atmosphere = Some(Atmosphere {
+                        density: ui_state.input_atmosphere[0].max(0.0) as f64,
+                        scale_height: ui_state.input_atmosphere[1].max(0.0) as f64 * 1000.0
+                    });
+                }
+            }
+
+            let mut is_spacecraft = body.spacecraft.is_some();
+            if ui.checkbox(im_str!("Spacecraft"), &mut is_spacecraft) {
+                game_state.bodies[body_idx].spacecraft = if is_spacecraft {
+                    Some(Spacecraft::default())
+                } else {
+                    None
+                };
+            }
+            let time = game_state.time;
+            if let Some(spacecraft) = game_state.bodies[body_idx].spacecraft.as_mut() {
+                build_spacecraft_ui(ui, spacecraft, time);
             }
 
             let cp = ColorPicker::new(im_str!("Color"), &mut ui_state.input_color)
@@ -159,6 +693,18 @@ fn build_body_ui(ui: &Ui, game_state: &mut GameState,
             if ui.button(im_str!("Delete"), [50.0, 20.0]) {
                 game_state.bodies.remove(body_idx);
                 ui_state.selected_body_idx = None;
+
+                let central = &mut game_state.integration.central_body;
+                *central = match *central {
+                    Some(idx) if idx == body_idx => None,
+                    Some(idx) if idx > body_idx => Some(idx - 1),
+                    other => other
+                };
+                game_state.frame = game_state.frame.reindex(|idx| {
+                    if idx == body_idx { None }
+                    else if idx > body_idx { Some(idx - 1) }
+                    else { Some(idx) }
+                });
             }
 
             if ui.button(im_str!("Close"), [50.0, 20.0]) {
@@ -167,6 +713,89 @@ fn build_body_ui(ui: &Ui, game_state: &mut GameState,
         });
 }
 
+// Engine settings and flight plan of a spacecraft, at simulated `time`
+fn build_spacecraft_ui(ui: &Ui, spacecraft: &mut Spacecraft, time: f64) {
+    let mut thrust = spacecraft.thrust as f32;
+    if ui.input_float(im_str!("Thrust (m/s^2)"), &mut thrust)
+        .enter_returns_true(true)
+        .build() {
+        spacecraft.thrust = thrust.max(0.0) as f64;
+    }
+
+    let mut budget = spacecraft.budget as f32;
+    if ui.input_float(im_str!("Delta-v budget (m/s)"), &mut budget)
+        .enter_returns_true(true)
+        .build() {
+        spacecraft.budget = budget.max(0.0) as f64;
+    }
+    ui.text(format!("Delta-v left: {:.1} m/s", spacecraft.remaining(time)));
+
+    let thrust = spacecraft.thrust;
+    let mut removed = None;
+    let mut moved = false;
+    for (idx, m) in spacecraft.maneuvers.iter_mut().enumerate() {
+        ui.separator();
+        ui.text(format!("Burn {}", idx + 1));
+
+        // Suffixes keep the fields of each burn apart
+        let mut start = (m.time / DAY) as f32;
+        if ui.input_float(&ImString::new(format!("Start (days)##burn{}", idx)), &mut start)
+            .enter_returns_true(true)
+            .build() {
+            m.time = start as f64 * DAY;
+            moved = true;
+        }
+
+        let mut direction = [m.direction.x as f32, m.direction.y as f32, m.direction.z as f32];
+        if ui.input_float3(&ImString::new(format!("Prograde, radial, normal##burn{}", idx)),
+                           &mut direction)
+            .enter_returns_true(true)
+            .build() {
+            m.direction = Vector3::new(direction[0] as f64, direction[1] as f64,
+                                       direction[2] as f64);
+        }
+
+        let mut delta_v = m.delta_v as f32;
+        if ui.input_float(&ImString::new(format!("Delta-v (m/s)##burn{}", idx)), &mut delta_v)
+            .enter_returns_true(true)
+            .build() {
+            m.delta_v = delta_v.max(0.0) as f64;
+        }
+
+        // Or set how long to burn at full thrust
+        if thrust > 0.0 {
+            let mut duration = (m.delta_v / thrust) as f32;
+            if ui.input_float(&ImString::new(format!("Duration (s)##burn{}", idx)),
+                              &mut duration)
+                .enter_returns_true(true)
+                .build() {
+                m.delta_v = duration.max(0.0) as f64 * thrust;
+            }
+        }
+
+        if ui.button(&ImString::new(format!("Delete##burn{}", idx)), [60.0, 20.0]) {
+            removed = Some(idx);
+        }
+    }
+    if let Some(idx) = removed {
+        spacecraft.maneuvers.remove(idx);
+    }
+    if moved {
+        spacecraft.sort();
+    }
+
+    ui.separator();
+    if ui.button(im_str!("Add burn"), [100.0, 20.0]) {
+        // Prograde, a day from now
+        spacecraft.maneuvers.push(Maneuver {
+            time: time + DAY,
+            direction: Vector3::new(1.0, 0.0, 0.0),
+            delta_v: 100.0
+        });
+        spacecraft.sort();
+    }
+}
+
 fn render_ui(ctx: &mut ggez::Context, ui: Ui,
              renderer: &mut Renderer<gfx_core::format::Rgba8, gfx_device_gl::Resources>) {
     let (factory, _, encoder, _, render_target) = graphics::gfx_objects(ctx);
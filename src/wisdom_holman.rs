@@ -0,0 +1,239 @@
+use ggez::nalgebra::{Point3, Vector3};
+
+use crate::body::{Body, G};
+use crate::forces::Forces;
+use crate::integrator::leapfrog;
+
+// Newton iterations allowed when solving Kepler's equation, after which
+// the drift is split into two halves instead
+const MAX_KEPLER_ITERATIONS: u32 = 50;
+const MAX_KEPLER_SPLITS: u32 = 16;
+
+// Index of the body everything orbits around: the chosen one if it still
+// exists, otherwise the most massive
+pub fn central_body(bodies: &[Body], chosen: Option<usize>) -> usize {
+    match chosen {
+        Some(idx) if idx < bodies.len() => idx,
+        _ => {
+            let mut central = 0;
+            for (idx, b) in bodies.iter().enumerate() {
+                if b.mass > bodies[central].mass { central = idx; }
+            }
+            central
+        }
+    }
+}
+
+// One Wisdom-Holman step in democratic heliocentric coordinates: positions
+// relative to the central body and velocities relative to the barycentre.
+// Each body's Keplerian orbit around the central body is followed exactly,
+// so only the much weaker pull between the other bodies (and the drift of
+// the central body) is split off and applied as kicks. Falls back to
+// leapfrog if the central body is massless, or if any body is pinned or
+// an external force acts, as that breaks the conservation of momentum the
+// coordinates rely on. Periodic domains fall back too, as the Kepler
+// orbits know nothing of the other images
+pub fn step(bodies: &mut [Body], dt: f64, central: Option<usize>, forces: &Forces) {
+    if bodies.len() < 2 { return; }
+    let c = central_body(bodies, central);
+    let m_c = bodies[c].mass;
+    let total_mass: f64 = bodies.iter().map(|b| b.mass).sum();
+    if m_c <= 0.0 || bodies.iter().any(|b| b.pinned) || forces.has_external(bodies)
+        || forces.periodic.is_some() {
+        leapfrog(bodies, dt, forces);
+        return;
+    }
+
+    let com = bodies.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.pos.coords)
+        / total_mass;
+    let v_com = bodies.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.v)
+        / total_mass;
+
+    // Everything but the central body, in heliocentric positions and
+    // barycentric velocities
+    let mut others: Vec<Body> = bodies.iter().enumerate()
+        .filter(|&(idx, _)| idx != c)
+        .map(|(_, b)| {
+            let mut b = b.clone();
+            b.pos = Point3::from(b.pos - bodies[c].pos);
+            b.v -= v_com;
+            b
+        })
+        .collect();
+
+    let mu = G * m_c;
+    interaction_kick(&mut others, dt / 2.0, &bodies[c], forces);
+    jump(&mut others, dt / 2.0, m_c);
+    for b in others.iter_mut() {
+        let (pos, v) = kepler_drift(b.pos.coords, b.v, mu, dt);
+        b.pos = Point3::from(pos);
+        b.v = v;
+    }
+    jump(&mut others, dt / 2.0, m_c);
+    interaction_kick(&mut others, dt / 2.0, &bodies[c], forces);
+
+    // Back to ordinary coordinates, with the barycentre drifting uniformly
+    let com = com + dt * v_com;
+    let weighted_pos = others.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.pos.coords);
+    let momentum = others.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.v);
+    let central_pos = com - weighted_pos / total_mass;
+
+    bodies[c].pos = Point3::from(central_pos);
+    bodies[c].v = v_com - momentum / m_c;
+    let mut others = others.into_iter();
+    for (idx, b) in bodies.iter_mut().enumerate() {
+        if idx == c { continue; }
+        let b_ = others.next().unwrap();
+        b.pos = Point3::from(b_.pos.coords + central_pos);
+        b.v = b_.v + v_com;
+    }
+}
+
+// Kick velocities by everything except the Newtonian pull of `central`,
+// which the Kepler drift already follows: the pull between the
+// non-central bodies, and any other forces between them and `central`
+fn interaction_kick(others: &mut [Body], dt: f64, central: &Body, forces: &Forces) {
+    forces.update_accels(others);
+
+    let momentum = others.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.v);
+    let mut central = central.clone();
+    central.pos = Point3::origin();
+    central.v = -momentum / central.mass;
+    for b in others.iter_mut() {
+        let d = central.pos - b.pos;
+        b.a += forces.pair_accel(b, &central) - b.accel_towards(&central, &d, forces.softening);
+    }
+
+    for b in others.iter_mut() {
+        b.v += dt * b.a;
+    }
+}
+
+// Shift positions by the motion of the central body relative to the
+// barycentre
+fn jump(others: &mut [Body], dt: f64, m_c: f64) {
+    let momentum = others.iter().fold(Vector3::new(0.0, 0.0, 0.0), |p, b| p + b.mass * b.v);
+    for b in others.iter_mut() {
+        b.pos += dt / m_c * momentum;
+    }
+}
+
+// Stumpff functions c2(z) and c3(z)
+fn stumpff(z: f64) -> (f64, f64) {
+    if z.abs() < 1e-2 {
+        let c2 = 1.0/2.0 - z/24.0 + z*z/720.0 - z*z*z/40320.0 + z*z*z*z/3628800.0;
+        let c3 = 1.0/6.0 - z/120.0 + z*z/5040.0 - z*z*z/362880.0 + z*z*z*z/39916800.0;
+        (c2, c3)
+    }
+    else if z > 0.0 {
+        let x = z.sqrt();
+        ((1.0 - x.cos()) / z, (x - x.sin()) / (z * x))
+    }
+    else {
+        let x = (-z).sqrt();
+        ((x.cosh() - 1.0) / -z, (x.sinh() - x) / (-z * x))
+    }
+}
+
+// Position and velocity after following a Kepler orbit with gravitational
+// parameter `mu` for `dt` seconds, using universal variables so elliptic
+// and hyperbolic orbits are handled alike
+fn kepler_drift(r0: Vector3<f64>, v0: Vector3<f64>, mu: f64,
+                dt: f64) -> (Vector3<f64>, Vector3<f64>) {
+    kepler_drift_split(r0, v0, mu, dt, 0)
+}
+
+fn kepler_drift_split(r0: Vector3<f64>, v0: Vector3<f64>, mu: f64, dt: f64,
+                      splits: u32) -> (Vector3<f64>, Vector3<f64>) {
+    match try_kepler_drift(r0, v0, mu, dt) {
+        Some(result) => result,
+        None if splits < MAX_KEPLER_SPLITS => {
+            let (r, v) = kepler_drift_split(r0, v0, mu, dt / 2.0, splits + 1);
+            kepler_drift_split(r, v, mu, dt / 2.0, splits + 1)
+        }
+        // Give up and leave the body where it is rather than fling it away
+        None => (r0, v0)
+    }
+}
+
+fn try_kepler_drift(r0: Vector3<f64>, v0: Vector3<f64>, mu: f64,
+                    dt: f64) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let r0_norm = r0.norm();
+    if r0_norm == 0.0 { return None; }
+
+    let sqrt_mu = mu.sqrt();
+    let sigma0 = r0.dot(&v0) / sqrt_mu;
+    let alpha = 2.0 / r0_norm - v0.norm_squared() / mu; // 1 / semi-major axis
+
+    // Solve the universal Kepler equation for chi by Newton's method
+    let mut chi = sqrt_mu * dt / r0_norm;
+    let mut converged = false;
+    for _ in 0..MAX_KEPLER_ITERATIONS {
+        let z = alpha * chi * chi;
+        let (c2, c3) = stumpff(z);
+        let f = sigma0 * chi * chi * c2 + (1.0 - alpha * r0_norm) * chi * chi * chi * c3
+            + r0_norm * chi - sqrt_mu * dt;
+        let df = sigma0 * chi * (1.0 - z * c3) + (1.0 - alpha * r0_norm) * chi * chi * c2
+            + r0_norm;
+        let delta = f / df;
+        if !delta.is_finite() { return None; }
+
+        chi -= delta;
+        if delta.abs() <= 1e-14 * chi.abs().max(1e-300) {
+            converged = true;
+            break;
+        }
+    }
+    if !converged { return None; }
+
+    let z = alpha * chi * chi;
+    let (c2, c3) = stumpff(z);
+    let f = 1.0 - chi * chi / r0_norm * c2;
+    let g = dt - chi * chi * chi / sqrt_mu * c3;
+    let r = f * r0 + g * v0;
+    let r_norm = r.norm();
+
+    let df = sqrt_mu / (r_norm * r0_norm) * chi * (z * c3 - 1.0);
+    let dg = 1.0 - chi * chi / r_norm * c2;
+    let v = df * r0 + dg * v0;
+    if !r.x.is_finite() || !v.x.is_finite() { return None; }
+    Some((r, v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3<f64>, b: Vector3<f64>, tolerance: f64) {
+        assert!((a - b).norm() <= tolerance * b.norm(), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn kepler_drift_round_trip() {
+        let mu = G * 1.989e30;
+        let r0 = Vector3::new(1.5e11, 0.0, 2e10);
+        // Elliptic, then hyperbolic
+        for &speed in &[35000.0, 60000.0] {
+            let v0 = Vector3::new(3000.0, speed, 0.0);
+            let (r, v) = kepler_drift(r0, v0, mu, 1e7);
+            assert!((r - r0).norm() > 1e10);
+
+            let (r, v) = kepler_drift(r, v, mu, -1e7);
+            assert_close(r, r0, 1e-10);
+            assert_close(v, v0, 1e-10);
+        }
+    }
+
+    #[test]
+    fn kepler_drift_circular_period() {
+        let mu = G * 1.989e30;
+        let radius = 1.5e11;
+        let r0 = Vector3::new(radius, 0.0, 0.0);
+        let v0 = Vector3::new(0.0, (mu / radius).sqrt(), 0.0);
+        let period = 2.0 * std::f64::consts::PI * (radius * radius * radius / mu).sqrt();
+
+        let (r, v) = kepler_drift(r0, v0, mu, period / 2.0);
+        assert_close(r, -r0, 1e-10);
+        assert_close(v, -v0, 1e-10);
+    }
+}